@@ -1,183 +1,836 @@
-fn main() {
+use anyhow::{anyhow, bail, Context, Error};
+use itertools::Itertools;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
+use std::thread;
+
+const CHUNK_ROWS: usize = 64 * 1024;
+
+fn main() -> Result<(), Error> {
     let input = include_str!("../../inputs/day3");
     let diagnostics = input
         .split('\n')
         .map(str::trim)
         .filter(|s| !s.is_empty())
         .collect::<Vec<_>>();
-    println!(
-        "Part 1 answer: {}",
-        calculate_epsilon_rate(&diagnostics) * calculate_gamma_rate(&diagnostics)
-    );
+
+    let mut explain = None;
+    let mut radix = 2;
+    let mut report_path = None;
+    let mut threads = thread::available_parallelism().map_or(1, usize::from);
+    let mut criteria = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--explain" => {
+                let format = args.next().context("--explain needs table or json")?;
+                explain = Some(format.parse::<ExplainFormat>()?);
+            }
+            "--radix" => {
+                radix = args.next().context("--radix needs a number")?.parse()?;
+                check_radix(radix)?;
+            }
+            "--input" => report_path = Some(args.next().context("--input needs a path")?),
+            "--threads" => {
+                threads = args.next().context("--threads needs a number")?.parse()?;
+            }
+            _ => criteria.push(arg.parse::<BitCriteria>()?),
+        }
+    }
+
+    // a report given with --input is streamed, so only the column counts are available; the
+    // rating filters need every row in memory
+    if let Some(path) = report_path {
+        if explain.is_some() || !criteria.is_empty() {
+            bail!("ratings are not available when streaming a report with --input");
+        }
+        let columns = if path == "-" {
+            count_columns(io::stdin().lock(), radix, threads, CHUNK_ROWS)?
+        } else {
+            let file = File::open(&path).with_context(|| format!("opening {}", path))?;
+            count_columns(BufReader::new(file), radix, threads, CHUNK_ROWS)?
+        };
+        println!(
+            "Part 1 answer: {}",
+            multiply(epsilon_rate(&columns, radix)?, gamma_rate(&columns, radix)?)?
+        );
+        return Ok(());
+    }
+
+    let (gamma, epsilon) = calculate_rates(&diagnostics, radix)?;
+    println!("Part 1 answer: {}", multiply(epsilon, gamma)?);
     println!(
         "Part 2 answer: {}",
-        calculate_oxygen_generator_rating(&diagnostics)
-            * calculate_co2_scrubber_rating(&diagnostics)
+        multiply(
+            calculate_oxygen_generator_rating(&diagnostics, radix)?,
+            calculate_co2_scrubber_rating(&diagnostics, radix)?
+        )?
     );
+    if explain.is_some() && criteria.is_empty() {
+        criteria = vec![BitCriteria::OXYGEN_GENERATOR, BitCriteria::CO2_SCRUBBER];
+    }
+    for criteria in criteria {
+        match explain {
+            Some(format) => {
+                let steps = explain_rating(&diagnostics, radix, criteria)
+                    .with_context(|| format!("rating {}", criteria))?;
+                match format {
+                    ExplainFormat::Table => print!("{}", render_table(criteria, &steps)),
+                    ExplainFormat::Json => println!("{}", render_json(criteria, &steps)),
+                }
+            }
+            None => {
+                let rating = calculate_rating(&diagnostics, radix, criteria)
+                    .with_context(|| format!("rating {}", criteria))?;
+                println!("Rating ({}): {}", criteria, rating);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+struct Digit(u32);
+
+impl Digit {
+    fn from_char(c: char, radix: u32) -> Result<Self, Error> {
+        c.to_digit(radix)
+            .map(Digit)
+            .ok_or_else(|| anyhow!("invalid radix {} digit {:?}", radix, c))
+    }
+
+    fn as_char(self) -> char {
+        char::from_digit(self.0, 36).unwrap_or('?')
+    }
 }
 
-enum BinaryNumber {
-    Zero,
-    One,
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Commonality {
+    Most,
+    Least,
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum TieBreak {
+    Lowest,
+    Highest,
+    KeepAll,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct BitCriteria {
+    commonality: Commonality,
+    tie_break: TieBreak,
+}
+
+impl BitCriteria {
+    const OXYGEN_GENERATOR: Self = Self {
+        commonality: Commonality::Most,
+        tie_break: TieBreak::Highest,
+    };
+
+    const CO2_SCRUBBER: Self = Self {
+        commonality: Commonality::Least,
+        tie_break: TieBreak::Lowest,
+    };
+
+    // the digits whose rows are kept, in ascending order
+    fn select(&self, column: &Column) -> Result<Vec<Digit>, Error> {
+        let tied = column.tied(self.commonality, true);
+        if tied.len() == 1 {
+            return Ok(tied);
+        }
+        match self.tie_break {
+            TieBreak::Lowest => Ok(tied[..1].to_vec()),
+            TieBreak::Highest => Ok(tied[tied.len() - 1..].to_vec()),
+            TieBreak::KeepAll => Ok(tied),
+            TieBreak::Error => bail!(
+                "tie between digits {}",
+                tied.iter().map(|d| d.as_char()).collect::<String>()
+            ),
+        }
+    }
+}
+
+impl FromStr for BitCriteria {
+    type Err = Error;
+
+    // `most`, `least`, `oxygen` or `co2`, optionally followed by a tie break of `:low` (or `:0`),
+    // `:high` (or `:1`), `:keep` or `:error`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (commonality, tie_break) = match s.split_once(':') {
+            Some((commonality, tie_break)) => (commonality, Some(tie_break)),
+            None => (s, None),
+        };
+        let preset = match commonality {
+            "most" | "oxygen" => Self::OXYGEN_GENERATOR,
+            "least" | "co2" => Self::CO2_SCRUBBER,
+            _ => bail!("Invalid criteria: {:?}", s),
+        };
+        let tie_break = match tie_break {
+            None => preset.tie_break,
+            Some("low" | "0") => TieBreak::Lowest,
+            Some("high" | "1") => TieBreak::Highest,
+            Some("keep") => TieBreak::KeepAll,
+            Some("error") => TieBreak::Error,
+            Some(other) => return Err(anyhow!("Invalid tie break: {:?}", other)),
+        };
+        Ok(Self {
+            commonality: preset.commonality,
+            tie_break,
+        })
+    }
+}
+
+impl fmt::Display for BitCriteria {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let commonality = match self.commonality {
+            Commonality::Most => "most",
+            Commonality::Least => "least",
+        };
+        let tie_break = match self.tie_break {
+            TieBreak::Lowest => "low",
+            TieBreak::Highest => "high",
+            TieBreak::KeepAll => "keep",
+            TieBreak::Error => "error",
+        };
+        write!(f, "{}:{}", commonality, tie_break)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 struct Column {
-    zeros: usize,
-    ones: usize,
+    counts: Vec<usize>,
 }
 
 impl Column {
-    fn with_initial_value(c: char) -> Self {
-        let mut this = Self::default();
-        this.add(c);
-        this
+    fn new(radix: u32) -> Self {
+        Self {
+            counts: vec![0; radix as usize],
+        }
     }
 
-    fn most_common(&self) -> BinaryNumber {
-        if self.zeros > self.ones {
-            BinaryNumber::Zero
-        } else {
-            BinaryNumber::One
-        }
+    fn radix(&self) -> u32 {
+        self.counts.len() as u32
     }
 
-    fn least_common(&self) -> BinaryNumber {
-        if self.zeros > self.ones {
-            BinaryNumber::One
-        } else {
-            BinaryNumber::Zero
+    // all digits sharing the highest (or lowest) count, in ascending order. Rating filters only
+    // consider digits that still occur, as filtering on an absent digit would discard every row
+    fn tied(&self, commonality: Commonality, present_only: bool) -> Vec<Digit> {
+        let candidates = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| !present_only || **count > 0)
+            .map(|(digit, count)| (Digit(digit as u32), *count))
+            .collect::<Vec<_>>();
+        let target = match commonality {
+            Commonality::Most => candidates.iter().map(|(_, count)| *count).max(),
+            Commonality::Least => candidates.iter().map(|(_, count)| *count).min(),
+        };
+        candidates
+            .into_iter()
+            .filter(|(_, count)| Some(*count) == target)
+            .map(|(digit, _)| digit)
+            .collect()
+    }
+
+    fn most_common(&self) -> Digit {
+        *self
+            .tied(Commonality::Most, false)
+            .last()
+            .unwrap_or(&Digit(0))
+    }
+
+    fn least_common(&self) -> Digit {
+        *self
+            .tied(Commonality::Least, false)
+            .first()
+            .unwrap_or(&Digit(0))
+    }
+
+    fn add(&mut self, c: char) -> Result<(), Error> {
+        let digit = Digit::from_char(c, self.radix())?;
+        self.counts[digit.0 as usize] += 1;
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Column) {
+        self.counts
+            .iter_mut()
+            .zip(&other.counts)
+            .for_each(|(count, other)| *count += other);
+    }
+}
+
+fn check_radix(radix: u32) -> Result<(), Error> {
+    if !(2..=36).contains(&radix) {
+        bail!("radix must be between 2 and 36, found {}", radix);
+    }
+    Ok(())
+}
+
+// rows and columns in errors are 1-based to match line numbers in the report
+fn validate(input: &[&str], radix: u32) -> Result<(), Error> {
+    check_radix(radix)?;
+    let width = match input.first() {
+        Some(first) => first.len(),
+        None => bail!("empty report"),
+    };
+    for (row, line) in input.iter().enumerate() {
+        validate_row(row, line, width, radix)?;
+    }
+    Ok(())
+}
+
+fn validate_row(row: usize, line: &str, width: usize, radix: u32) -> Result<(), Error> {
+    if line.is_empty() {
+        bail!("row {}: empty row", row + 1);
+    }
+    if let Some((col, c)) = line.char_indices().find(|(_, c)| !c.is_digit(radix)) {
+        bail!(
+            "row {}, column {}: invalid radix {} digit {:?}",
+            row + 1,
+            col + 1,
+            radix,
+            c
+        );
+    }
+    if line.len() != width {
+        bail!(
+            "row {}: expected {} digits but found {}",
+            row + 1,
+            width,
+            line.len()
+        );
+    }
+    Ok(())
+}
+
+// counts every column in a single pass over the report without holding it in memory: rows are
+// read in chunks, up to `threads` chunks are counted in parallel and the partial counts merged
+fn count_columns<R: BufRead>(
+    reader: R,
+    radix: u32,
+    threads: usize,
+    chunk_rows: usize,
+) -> Result<Vec<Column>, Error> {
+    check_radix(radix)?;
+    let mut lines = reader.lines();
+    let mut columns: Option<Vec<Column>> = None;
+    let mut rows_read = 0;
+    loop {
+        let mut chunks = vec![];
+        for _ in 0..threads.max(1) {
+            let mut chunk = Vec::with_capacity(chunk_rows.min(CHUNK_ROWS));
+            for line in lines.by_ref() {
+                let line = line?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    chunk.push(line.to_string());
+                }
+                if chunk.len() == chunk_rows {
+                    break;
+                }
+            }
+            if chunk.is_empty() {
+                break;
+            }
+            chunks.push((rows_read, chunk));
+            rows_read += chunks[chunks.len() - 1].1.len();
+        }
+        if chunks.is_empty() {
+            break;
+        }
+
+        let partials = thread::scope(|scope| {
+            chunks
+                .iter()
+                .map(|(first_row, chunk)| {
+                    scope.spawn(move || count_chunk(chunk, *first_row, radix))
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        for ((first_row, _), partial) in chunks.iter().zip(partials) {
+            match columns.as_mut() {
+                Some(columns) if columns.len() != partial.len() => bail!(
+                    "row {}: expected {} digits but found {}",
+                    first_row + 1,
+                    columns.len(),
+                    partial.len()
+                ),
+                Some(columns) => columns
+                    .iter_mut()
+                    .zip(&partial)
+                    .for_each(|(column, partial)| column.merge(partial)),
+                None => columns = Some(partial),
+            }
         }
     }
+    columns.ok_or_else(|| anyhow!("empty report"))
+}
 
-    fn add(&mut self, c: char) {
-        if c == '0' {
-            self.zeros += 1;
-        } else if c == '1' {
-            self.ones += 1;
+fn count_chunk(chunk: &[String], first_row: usize, radix: u32) -> Result<Vec<Column>, Error> {
+    let width = chunk.first().map(String::len).unwrap_or_default();
+    let mut columns = vec![Column::new(radix); width];
+    for (idx, line) in chunk.iter().enumerate() {
+        validate_row(first_row + idx, line, width, radix)?;
+        for (column, c) in columns.iter_mut().zip(line.chars()) {
+            column.add(c)?;
         }
     }
+    Ok(columns)
 }
 
-fn parse(input: &[&str]) -> Vec<Column> {
+fn parse(input: &[&str], radix: u32) -> Result<Vec<Column>, Error> {
     let mut columns = Vec::<Column>::new();
-    input.iter().for_each(|input| {
-        input
-            .char_indices()
-            .for_each(|(idx, c)| match columns.get_mut(idx) {
-                Some(col) => col.add(c),
-                None => columns.insert(idx, Column::with_initial_value(c)),
-            })
-    });
-    columns
+    for input in input {
+        for (idx, c) in input.char_indices() {
+            if columns.len() <= idx {
+                columns.push(Column::new(radix));
+            }
+            columns[idx].add(c)?;
+        }
+    }
+    Ok(columns)
+}
+
+// appends a digit to a number read most significant digit first; wide rows don't fit in a usize
+fn push_digit(acc: usize, digit: Digit, radix: u32) -> Result<usize, Error> {
+    acc.checked_mul(radix as usize)
+        .and_then(|acc| acc.checked_add(digit.0 as usize))
+        .ok_or_else(|| anyhow!("value doesn't fit in {} bits", usize::BITS))
+}
+
+fn multiply(a: usize, b: usize) -> Result<usize, Error> {
+    a.checked_mul(b)
+        .ok_or_else(|| anyhow!("answer {} * {} doesn't fit in {} bits", a, b, usize::BITS))
 }
 
-fn parse_binary(input: &str) -> usize {
-    input.chars().fold(0, |acc, item| match item {
-        '0' => acc << 1,
-        '1' => (acc << 1) | 1,
-        _ => acc,
+fn parse_number(input: &str, radix: u32) -> Result<usize, Error> {
+    input.chars().try_fold(0, |acc, item| {
+        push_digit(acc, Digit::from_char(item, radix)?, radix)
     })
 }
 
-fn calculate_gamma_rate(input: &[&str]) -> usize {
-    parse(input)
+fn gamma_rate(columns: &[Column], radix: u32) -> Result<usize, Error> {
+    columns
         .iter()
-        .fold(0, |acc, item| match item.most_common() {
-            BinaryNumber::Zero => acc << 1,
-            BinaryNumber::One => (acc << 1) | 1,
-        })
+        .try_fold(0, |acc, item| push_digit(acc, item.most_common(), radix))
+        .context("gamma rate")
 }
 
-fn calculate_epsilon_rate(input: &[&str]) -> usize {
-    parse(input)
+fn epsilon_rate(columns: &[Column], radix: u32) -> Result<usize, Error> {
+    columns
         .iter()
-        .fold(0, |acc, item| match item.least_common() {
-            BinaryNumber::Zero => acc << 1,
-            BinaryNumber::One => (acc << 1) | 1,
-        })
+        .try_fold(0, |acc, item| push_digit(acc, item.least_common(), radix))
+        .context("epsilon rate")
 }
 
-fn calculate_oxygen_generator_rating(input: &[&str]) -> usize {
-    let mut parsed: Vec<Column>;
+// returns the gamma and epsilon rates from a single count of the columns
+fn calculate_rates(input: &[&str], radix: u32) -> Result<(usize, usize), Error> {
+    validate(input, radix)?;
+    let columns = parse(input, radix)?;
+    Ok((gamma_rate(&columns, radix)?, epsilon_rate(&columns, radix)?))
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct FilterStep<'a> {
+    position: usize,
+    counts: Vec<usize>,
+    selected: Vec<Digit>,
+    survivors: Vec<&'a str>,
+    radix: u32,
+}
+
+fn explain_rating<'a>(
+    input: &[&'a str],
+    radix: u32,
+    criteria: BitCriteria,
+) -> Result<Vec<FilterStep<'a>>, Error> {
+    validate(input, radix)?;
+    let mut steps = vec![];
     let mut input = input.to_vec();
-    for idx in 0..input.get(0).map(|s| s.len()).unwrap_or_default() {
-        parsed = parse(&input);
-        if parsed[idx].ones >= parsed[idx].zeros {
-            input = input
-                .into_iter()
-                .filter(|s| s.chars().nth(idx).unwrap_or_default() == '1')
-                .collect::<Vec<_>>();
-        } else {
-            input = input
-                .into_iter()
-                .filter(|s| s.chars().nth(idx).unwrap_or_default() == '0')
-                .collect::<Vec<_>>();
-        }
-        if input.len() == 1 {
-            return parse_binary(input.into_iter().next().unwrap_or_default());
+    for idx in 0..input[0].len() {
+        let column = parse(&input, radix)?.swap_remove(idx);
+        let selected = criteria
+            .select(&column)
+            .with_context(|| format!("position {}", idx))?;
+        input.retain(|s| {
+            s.chars()
+                .nth(idx)
+                .and_then(|c| c.to_digit(radix))
+                .is_some_and(|d| selected.contains(&Digit(d)))
+        });
+        steps.push(FilterStep {
+            position: idx,
+            counts: column.counts,
+            selected,
+            survivors: input.clone(),
+            radix,
+        });
+        if input.len() <= 1 {
+            break;
         }
     }
-    0
+    Ok(steps)
 }
 
-fn calculate_co2_scrubber_rating(input: &[&str]) -> usize {
-    let mut parsed: Vec<Column>;
-    let mut input = input.to_vec();
-    for idx in 0..input.get(0).map(|s| s.len()).unwrap_or_default() {
-        parsed = parse(&input);
-        if parsed[idx].zeros <= parsed[idx].ones {
-            input = input
-                .into_iter()
-                .filter(|s| s.chars().nth(idx).unwrap_or_default() == '0')
-                .collect::<Vec<_>>();
-        } else {
-            input = input
-                .into_iter()
-                .filter(|s| s.chars().nth(idx).unwrap_or_default() == '1')
-                .collect::<Vec<_>>();
+fn rating_from_steps(steps: &[FilterStep]) -> Result<usize, Error> {
+    match steps.last() {
+        Some(step) => match step.survivors.as_slice() {
+            [row] => parse_number(row, step.radix),
+            [] => bail!("no rows left after filtering position {}", step.position),
+            rows => bail!("{} rows left after filtering every position", rows.len()),
+        },
+        None => bail!("empty report"),
+    }
+}
+
+fn calculate_rating(input: &[&str], radix: u32, criteria: BitCriteria) -> Result<usize, Error> {
+    rating_from_steps(&explain_rating(input, radix, criteria)?)
+}
+
+fn calculate_oxygen_generator_rating(input: &[&str], radix: u32) -> Result<usize, Error> {
+    calculate_rating(input, radix, BitCriteria::OXYGEN_GENERATOR)
+}
+
+fn calculate_co2_scrubber_rating(input: &[&str], radix: u32) -> Result<usize, Error> {
+    calculate_rating(input, radix, BitCriteria::CO2_SCRUBBER)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum ExplainFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for ExplainFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(ExplainFormat::Table),
+            "json" => Ok(ExplainFormat::Json),
+            _ => bail!("Invalid explain format: {:?}", s),
         }
-        if input.len() == 1 {
-            return parse_binary(input.into_iter().next().unwrap_or_default());
+    }
+}
+
+const MAX_LISTED_SURVIVORS: usize = 8;
+
+fn render_table(criteria: BitCriteria, steps: &[FilterStep]) -> String {
+    let counts = steps
+        .iter()
+        .map(|step| step.counts.iter().map(usize::to_string).join("/"))
+        .collect::<Vec<_>>();
+    let counts_width = counts.iter().map(String::len).max().unwrap_or_default();
+    let mut out = format!("Rating ({})\n", criteria);
+    out.push_str(&format!(
+        "pos  {:<width$}  keep   rows  survivors\n",
+        "counts",
+        width = counts_width
+    ));
+    for (step, counts) in steps.iter().zip(counts) {
+        let keep = step
+            .selected
+            .iter()
+            .map(|d| d.as_char())
+            .collect::<String>();
+        let mut survivors = step
+            .survivors
+            .iter()
+            .take(MAX_LISTED_SURVIVORS)
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if step.survivors.len() > MAX_LISTED_SURVIVORS {
+            survivors.push_str(&format!(
+                " ... ({} more)",
+                step.survivors.len() - MAX_LISTED_SURVIVORS
+            ));
         }
+        out.push_str(&format!(
+            "{:>3}  {:>width$}  {:>4}  {:>5}  {}\n",
+            step.position,
+            counts,
+            keep,
+            step.survivors.len(),
+            survivors,
+            width = counts_width
+        ));
     }
-    0
+    match rating_from_steps(steps) {
+        Ok(rating) => out.push_str(&format!("Result: {}\n", rating)),
+        Err(e) => out.push_str(&format!("Result: error: {}\n", e)),
+    }
+    out
+}
+
+fn render_json(criteria: BitCriteria, steps: &[FilterStep]) -> String {
+    let steps_json = steps
+        .iter()
+        .map(|step| {
+            let counts = step.counts.iter().map(usize::to_string).join(",");
+            let keep = step
+                .selected
+                .iter()
+                .map(|d| format!("\"{}\"", d.as_char()))
+                .join(",");
+            let survivors = step
+                .survivors
+                .iter()
+                .map(|row| format!("\"{}\"", row))
+                .collect::<Vec<_>>()
+                .join(",");
+            format!(
+                "{{\"position\":{},\"counts\":[{}],\"keep\":[{}],\"rows\":{},\"survivors\":[{}]}}",
+                step.position,
+                counts,
+                keep,
+                step.survivors.len(),
+                survivors
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let rating = match rating_from_steps(steps) {
+        Ok(rating) => rating.to_string(),
+        Err(_) => "null".to_string(),
+    };
+    format!(
+        "{{\"criteria\":\"{}\",\"steps\":[{}],\"rating\":{}}}",
+        criteria, steps_json, rating
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
     const INPUT: &[&str] = &[
         "00100", "11110", "10110", "10111", "10101", "01111", "00111", "11100", "10000", "11001",
         "00010", "01010",
     ];
 
+    const TERNARY_INPUT: &[&str] = &["0120", "2211", "1021", "2100", "0222", "2012", "1201"];
+
     #[test]
     fn test_gamma() {
         let expected = 22;
-        let actual = calculate_gamma_rate(INPUT);
+        let actual = calculate_rates(INPUT, 2).unwrap().0;
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn test_epsilon() {
         let expected = 9;
-        let actual = calculate_epsilon_rate(INPUT);
+        let actual = calculate_rates(INPUT, 2).unwrap().1;
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn test_oxygen() {
         let expected = 23;
-        let actual = calculate_oxygen_generator_rating(INPUT);
+        let actual = calculate_oxygen_generator_rating(INPUT, 2).unwrap();
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn test_scrubber() {
         let expected = 10;
-        let actual = calculate_co2_scrubber_rating(INPUT);
+        let actual = calculate_co2_scrubber_rating(INPUT, 2).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test_case("most", BitCriteria::OXYGEN_GENERATOR)]
+    #[test_case("oxygen", BitCriteria::OXYGEN_GENERATOR)]
+    #[test_case("co2:0", BitCriteria::CO2_SCRUBBER)]
+    #[test_case("least:low", BitCriteria::CO2_SCRUBBER)]
+    #[test_case("least:keep", BitCriteria { commonality: Commonality::Least, tie_break: TieBreak::KeepAll })]
+    #[test_case("most:error", BitCriteria { commonality: Commonality::Most, tie_break: TieBreak::Error })]
+    fn test_parse_criteria(input: &str, expected: BitCriteria) {
+        let actual = input.parse::<BitCriteria>().unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_parse_criteria_invalid() {
+        assert!("middle".parse::<BitCriteria>().is_err());
+        assert!("most:2".parse::<BitCriteria>().is_err());
+    }
+
+    #[test]
+    fn test_rating_tie_break() {
+        // oxygen filtering of INPUT ends on a tie between 10110 and 10111
+        let most_zero = "most:0".parse().unwrap();
+        let most_error = "most:error".parse().unwrap();
+        assert_eq!(
+            23,
+            calculate_rating(INPUT, 2, BitCriteria::OXYGEN_GENERATOR).unwrap()
+        );
+        assert_eq!(22, calculate_rating(INPUT, 2, most_zero).unwrap());
+        assert!(calculate_rating(INPUT, 2, most_error).is_err());
+    }
+
+    #[test_case(&[], "empty report")]
+    #[test_case(&["0101", ""], "row 2: empty row")]
+    #[test_case(&["0101", "011"], "row 2: expected 4 digits but found 3")]
+    #[test_case(&["0101", "01101"], "row 2: expected 4 digits but found 5")]
+    #[test_case(&["0101", "0111", "01x1"], "row 3, column 3: invalid radix 2 digit 'x'")]
+    fn test_validate_rejects(input: &[&str], expected: &str) {
+        let actual = validate(input, 2).unwrap_err().to_string();
+        assert_eq!(expected, actual);
+        assert!(calculate_rates(input, 2).is_err());
+        assert!(calculate_oxygen_generator_rating(input, 2).is_err());
+    }
+
+    #[test]
+    fn test_rating_never_narrows() {
+        let input = &["0101", "0101"];
+        let actual = calculate_oxygen_generator_rating(input, 2).unwrap_err();
+        assert_eq!(
+            "2 rows left after filtering every position",
+            actual.to_string()
+        );
+    }
+
+    #[test]
+    fn test_explain_oxygen() {
+        let steps = explain_rating(INPUT, 2, BitCriteria::OXYGEN_GENERATOR).unwrap();
+        let actual = steps
+            .iter()
+            .map(|step| {
+                (
+                    step.position,
+                    step.counts.clone(),
+                    step.selected.clone(),
+                    step.survivors.len(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let expected = vec![
+            (0, vec![5, 7], vec![Digit(1)], 7),
+            (1, vec![4, 3], vec![Digit(0)], 4),
+            (2, vec![1, 3], vec![Digit(1)], 3),
+            (3, vec![1, 2], vec![Digit(1)], 2),
+            (4, vec![1, 1], vec![Digit(1)], 1),
+        ];
+        assert_eq!(expected, actual);
+        assert_eq!(vec!["10111"], steps[4].survivors);
+    }
+
+    #[test]
+    fn test_explain_keep_all() {
+        let input = &["01", "10"];
+        let criteria = "least:keep".parse().unwrap();
+        let steps = explain_rating(input, 2, criteria).unwrap();
+        assert_eq!(vec![Digit(0), Digit(1)], steps[0].selected);
+        assert_eq!(input.to_vec(), steps[0].survivors);
+    }
+
+    #[test]
+    fn test_render_json() {
+        let input = &["01", "10", "11"];
+        let steps = explain_rating(input, 2, BitCriteria::CO2_SCRUBBER).unwrap();
+        let expected = r#"{"criteria":"least:low","steps":[{"position":0,"counts":[1,2],"keep":["0"],"rows":1,"survivors":["01"]}],"rating":1}"#;
+        let actual = render_json(BitCriteria::CO2_SCRUBBER, &steps);
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_ternary_rates() {
+        let (gamma, epsilon) = calculate_rates(TERNARY_INPUT, 3).unwrap();
+        // most common per column: 2, 2, 2 (0/1/2 tie -> highest), 1
+        assert_eq!(2 * 27 + 2 * 9 + 2 * 3 + 1, gamma);
+        // least common per column: 0 (0/1 tie -> lowest), 0, 0 (tie -> lowest), 0
+        assert_eq!(0, epsilon);
+    }
+
+    #[test]
+    fn test_ternary_ratings() {
+        // most: 2 -> {2211, 2100, 2012}, then 0/1/2 tie -> 2 -> 2211
+        assert_eq!(
+            2 * 27 + 2 * 9 + 3 + 1,
+            calculate_oxygen_generator_rating(TERNARY_INPUT, 3).unwrap()
+        );
+        // least: 0/1 tie -> 0 -> {0120, 0222}, then 1/2 tie -> 1 -> 0120
+        assert_eq!(
+            9 + 2 * 3,
+            calculate_co2_scrubber_rating(TERNARY_INPUT, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hexadecimal() {
+        let input = &["fA", "F0", "1a"];
+        assert_eq!(0xfa, calculate_rates(input, 16).unwrap().0);
+        assert_eq!(0xfa, calculate_oxygen_generator_rating(input, 16).unwrap());
+        assert!(validate(input, 10).is_err());
+        assert!(calculate_rates(input, 37).is_err());
+    }
+
+    #[test_case(2, 70)]
+    #[test_case(16, 17)]
+    fn test_too_wide_for_usize(radix: u32, width: usize) {
+        let rows = ["1", "0", "1"].map(|digit| format!("1{}", digit.repeat(width - 1)));
+        let input = rows.iter().map(String::as_str).collect::<Vec<_>>();
+        let error = calculate_rates(&input, radix).unwrap_err();
+        assert_eq!("gamma rate", error.to_string());
+        assert!(calculate_oxygen_generator_rating(&input, radix).is_err());
+        let columns = count_columns(rows.join("\n").as_bytes(), radix, 2, 1).unwrap();
+        assert!(gamma_rate(&columns, radix).is_err());
+    }
+
+    #[test]
+    fn test_widest_numbers() {
+        assert_eq!(usize::MAX, parse_number(&"1".repeat(64), 2).unwrap());
+        assert_eq!(usize::MAX, parse_number(&"f".repeat(16), 16).unwrap());
+        assert!(parse_number(&"1".repeat(65), 2).is_err());
+        assert!(parse_number(&format!("1{}", "0".repeat(16)), 16).is_err());
+    }
+
+    #[test_case(1, 1)]
+    #[test_case(1, 5)]
+    #[test_case(3, 5)]
+    #[test_case(4, 100)]
+    fn test_count_columns_matches_parse(threads: usize, chunk_rows: usize) {
+        let report = INPUT.join("\n");
+        let columns = count_columns(report.as_bytes(), 2, threads, chunk_rows).unwrap();
+        assert_eq!(parse(INPUT, 2).unwrap(), columns);
+        assert_eq!(22, gamma_rate(&columns, 2).unwrap());
+        assert_eq!(9, epsilon_rate(&columns, 2).unwrap());
+    }
+
+    #[test]
+    fn test_count_columns_skips_blank_lines() {
+        let report = "\n00100\n\n  11110\n10110\n\n";
+        let columns = count_columns(report.as_bytes(), 2, 2, 1).unwrap();
+        assert_eq!(parse(&INPUT[..3], 2).unwrap(), columns);
+    }
+
+    #[test_case("", "empty report")]
+    #[test_case("0101\n0111\n0101\n011", "row 4: expected 4 digits but found 3")]
+    #[test_case(
+        "0101\n0111\n0101\n01101\n01101",
+        "row 4: expected 4 digits but found 5"
+    )]
+    #[test_case("0101\n0111\n0121", "row 3, column 3: invalid radix 2 digit '2'")]
+    fn test_count_columns_rejects(report: &str, expected: &str) {
+        // chunks of two rows so that some errors are only found when merging
+        let actual = count_columns(report.as_bytes(), 2, 2, 2).unwrap_err();
+        assert_eq!(expected, actual.to_string());
+    }
 }
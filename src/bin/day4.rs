@@ -1,256 +1,2146 @@
-const BOARD_SIZE: usize = 5;
+use anyhow::{anyhow, bail, Context, Error};
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-fn main() {
-    let input = include_str!("../../inputs/day4");
-    let input = input
-        .split('\n')
-        .map(str::trim)
-        .filter(|s| !s.is_empty())
-        .collect::<Vec<_>>();
-    let draw_numbers = input[0].split(',').collect::<Vec<_>>();
-    let input = &input[1..];
-    let boards = {
-        let mut boards = Vec::<Board>::new();
-        for offset in (0..input.len()).step_by(BOARD_SIZE) {
-            match input.get(offset..offset + BOARD_SIZE) {
-                Some(input) => boards.push(Board::parse(input)),
-                None => break,
+fn main() -> Result<(), Error> {
+    let mut input_path = None;
+    let mut size = None;
+    let mut mixed_sizes = false;
+    let mut patterns = LINES.to_vec();
+    let mut show_results = false;
+    let mut trials = None;
+    let mut seed = 0;
+    let mut first_order = None;
+    let mut last_order = None;
+    let mut generate = None;
+    let mut generator = Generator::default();
+    let mut replay = false;
+    let mut speed = DEFAULT_SPEED;
+    let mut serve_port = None;
+    let mut players = 2;
+    let mut resume = None;
+    let mut save = None;
+    let mut stop_after = None;
+    let mut undo = 0;
+    let mut style = Style::Ansi;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => size = Some(parse_size(&args.next().context("--size needs ROWSxCOLS")?)?),
+            "--mixed-sizes" => mixed_sizes = true,
+            "--win" => patterns = parse_patterns(&args.next().context("--win needs patterns")?)?,
+            "--results" => show_results = true,
+            "--simulate" => {
+                trials = Some(
+                    args.next()
+                        .context("--simulate needs a number of trials")?
+                        .parse()?,
+                );
+            }
+            "--seed" => seed = args.next().context("--seed needs a number")?.parse()?,
+            "--first-order" => {
+                first_order = Some(
+                    args.next()
+                        .context("--first-order needs a board")?
+                        .parse()?,
+                );
+            }
+            "--last-order" => {
+                last_order = Some(args.next().context("--last-order needs a board")?.parse()?);
+            }
+            "--input" => input_path = Some(args.next().context("--input needs a path")?),
+            "--generate" => {
+                generate = Some(
+                    args.next()
+                        .context("--generate needs a number of boards")?
+                        .parse()?,
+                );
             }
+            "--range" => {
+                let range = args.next().context("--range needs MIN-MAX")?;
+                let (min, max) = range
+                    .split_once('-')
+                    .ok_or_else(|| anyhow!("Invalid range: {:?}", range))?;
+                generator.min = min.parse()?;
+                generator.max = max.parse()?;
+            }
+            "--allow-duplicates" => generator.unique_squares = false,
+            "--allow-identical" => generator.unique_boards = false,
+            "--column-ranges" => generator.column_ranges = true,
+            "--replay" => replay = true,
+            "--no-color" => style = Style::Plain,
+            "--speed" => {
+                speed = Duration::from_millis(
+                    args.next().context("--speed needs milliseconds")?.parse()?,
+                );
+            }
+            "--serve" => serve_port = Some(args.next().context("--serve needs a port")?.parse()?),
+            "--resume" => resume = Some(args.next().context("--resume needs a path")?),
+            "--save" => save = Some(args.next().context("--save needs a path")?),
+            "--stop-after" => {
+                stop_after = Some(args.next().context("--stop-after needs a draw")?.parse()?);
+            }
+            "--undo" => {
+                undo = args
+                    .next()
+                    .context("--undo needs a number of draws")?
+                    .parse()?
+            }
+            "--players" => {
+                players = args.next().context("--players needs a number")?.parse()?;
+            }
+            _ => bail!("Unknown argument: {:?}", arg),
         }
-        boards
+    }
+
+    if let Some((rows, cols)) = size {
+        generator.rows = rows;
+        generator.cols = cols;
+    }
+    if let Some(boards) = generate {
+        let mut rng = Rng::new(seed);
+        let boards = generator.generate(boards, &mut rng)?;
+        print!("{}", generator.render_game(&boards, &mut rng));
+        return Ok(());
+    }
+    // only ever listens on localhost
+    if let Some(port) = serve_port {
+        let listener = TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, port))?;
+        println!("Listening on {}", listener.local_addr()?);
+        let mut rng = Rng::new(seed);
+        match serve(&listener, players, &generator, &patterns, speed, &mut rng)? {
+            Some((name, score)) => println!("{} wins with a score of {}", name, score),
+            None => println!("Nobody won"),
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = &resume {
+        let snapshot = std::fs::read_to_string(path)
+            .with_context(|| format!("reading {}", path))?
+            .parse::<Snapshot>()
+            .with_context(|| format!("restoring {}", path))?;
+        let boards = snapshot.initial_boards();
+        let mut replay = snapshot.restore(&boards)?;
+        replay.undo(undo);
+        return match save {
+            Some(path) => save_snapshot(&replay, &path),
+            None => replay.run(
+                std::io::stdin().lock(),
+                &mut std::io::stdout(),
+                speed,
+                style,
+            ),
+        };
+    }
+
+    let input = match input_path {
+        Some(path) => {
+            std::fs::read_to_string(&path).with_context(|| format!("reading {}", path))?
+        }
+        None => include_str!("../../inputs/day4").to_string(),
     };
 
-    println!("Part 1 answer: {}", play_bingo(&boards, &draw_numbers));
+    let board_size = match (size, mixed_sizes) {
+        (Some(_), true) => bail!("--mixed-sizes can't be used with --size"),
+        (Some((rows, cols)), false) => BoardSize::Fixed(rows, cols),
+        (None, false) => BoardSize::FromFirst,
+        (None, true) => BoardSize::Any,
+    };
+    let (draw_numbers, boards) = parse_game(&input, board_size, !generator.unique_squares)?;
+    check_patterns(&patterns, &boards)?;
+    if replay || save.is_some() {
+        let mut replay = Replay::new(&boards, &draw_numbers, &patterns);
+        replay.jump(stop_after.unwrap_or_default());
+        replay.undo(undo);
+        return match save {
+            Some(path) => save_snapshot(&replay, &path),
+            None => replay.run(
+                std::io::stdin().lock(),
+                &mut std::io::stdout(),
+                speed,
+                style,
+            ),
+        };
+    }
+    println!(
+        "Part 1 answer: {}",
+        play_bingo(&boards, &draw_numbers, &patterns)
+    );
     println!(
         "Part 2 answer: {}",
-        play_bingo_to_lose(&boards, &draw_numbers)
+        play_bingo_to_lose(&boards, &draw_numbers, &patterns)
     );
+    if show_results {
+        print!(
+            "{}",
+            render_results(&boards, &play_all(&boards, &draw_numbers, &patterns))
+        );
+    }
+    if let Some(trials) = trials {
+        let odds = simulate(&boards, &draw_numbers, &patterns, trials, seed);
+        print!("{}", render_odds(&odds, trials));
+    }
+    // boards are numbered from 1 on the command line, as in the results table
+    if let Some(board) = first_order {
+        let board = board_index(board, &boards)?;
+        match shortest_win_first(&boards, &draw_numbers, &patterns, board) {
+            Some(order) => println!(
+                "Board {} wins first after {} draws: {}",
+                board + 1,
+                order.wins_at,
+                order.draws.iter().map(usize::to_string).join(",")
+            ),
+            None => println!("Board {} can't win first", board + 1),
+        }
+    }
+    if let Some(board) = last_order {
+        let board = board_index(board, &boards)?;
+        match order_to_win_last(&boards, &draw_numbers, &patterns, board, SEARCH_BUDGET) {
+            Ok(order) => println!(
+                "Board {} wins last on draw {} of {} ({} search): {}",
+                board + 1,
+                order.wins_at,
+                order.draws.len(),
+                if order.exact { "exact" } else { "heuristic" },
+                order.draws.iter().map(usize::to_string).join(",")
+            ),
+            Err(Exhausted { exact: true }) => {
+                println!("No order of the draws makes board {} win last", board + 1)
+            }
+            Err(Exhausted { exact: false }) => println!(
+                "Board {} wins last: none found (heuristic search)",
+                board + 1
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn parse_size(s: &str) -> Result<(usize, usize), Error> {
+    let (rows, cols) = s
+        .split_once('x')
+        .ok_or_else(|| anyhow!("Invalid size: {:?}", s))?;
+    let size = (rows.parse()?, cols.parse()?);
+    if size.0 == 0 || size.1 == 0 {
+        bail!("Invalid size: {:?}", s);
+    }
+    Ok(size)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum BoardSize {
+    // given on the command line
+    Fixed(usize, usize),
+    // whatever size the first board is, so a truncated board is an error rather than a smaller
+    // board
+    FromFirst,
+    // every board can be a different size
+    Any,
+}
+
+// boards are separated by blank lines, or cut into `rows` rows at a time when the size is fixed;
+// errors point at the 1-based line of the input
+fn parse_game(
+    input: &str,
+    size: BoardSize,
+    allow_duplicates: bool,
+) -> Result<(Vec<usize>, Vec<Board>), Error> {
+    let mut lines = input
+        .split('\n')
+        .map(str::trim)
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line));
+    let (draw_line, draws) = lines
+        .find(|(_, s)| !s.is_empty())
+        .ok_or_else(|| anyhow!("missing draw numbers"))?;
+    let draw_numbers = draws
+        .split(',')
+        .map(str::trim)
+        .map(|n| {
+            n.parse::<usize>()
+                .map_err(|_| anyhow!("line {}: invalid draw number {:?}", draw_line, n))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let lines = lines.collect::<Vec<_>>();
+    let groups = match size {
+        BoardSize::Fixed(rows, _) => lines
+            .into_iter()
+            .filter(|(_, s)| !s.is_empty())
+            .collect::<Vec<_>>()
+            .chunks(rows)
+            .map(<[_]>::to_vec)
+            .collect::<Vec<_>>(),
+        BoardSize::FromFirst | BoardSize::Any => lines
+            .split(|(_, s)| s.is_empty())
+            .filter(|group| !group.is_empty())
+            .map(<[_]>::to_vec)
+            .collect::<Vec<_>>(),
+    };
+    let mut boards = Vec::with_capacity(groups.len());
+    for group in groups {
+        let board = Board::parse_numbered(&group, allow_duplicates)?;
+        let expected = match size {
+            BoardSize::Fixed(rows, cols) => Some((rows, cols)),
+            BoardSize::FromFirst => boards.first().map(|first: &Board| (first.rows, first.cols)),
+            BoardSize::Any => None,
+        };
+        if let Some((rows, cols)) = expected {
+            if (board.rows, board.cols) != (rows, cols) {
+                bail!(
+                    "line {}: expected a {}x{} board but found {}x{}",
+                    group[0].0,
+                    rows,
+                    cols,
+                    board.rows,
+                    board.cols
+                );
+            }
+        }
+        boards.push(board);
+    }
+    if boards.is_empty() {
+        bail!("no boards after the draw numbers on line {}", draw_line);
+    }
+    Ok((draw_numbers, boards))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Board {
+    rows: usize,
+    cols: usize,
+    // row-major
+    squares: Vec<Square>,
+}
+
+impl Board {
+    fn parse_numbered(input: &[(usize, &str)], allow_duplicates: bool) -> Result<Self, Error> {
+        let mut squares = vec![];
+        let mut cols = None;
+        let mut seen = HashMap::new();
+        for (line, text) in input {
+            let row = text
+                .split_whitespace()
+                .map(|n| {
+                    n.parse::<usize>()
+                        .map_err(|_| anyhow!("line {}: invalid number {:?}", line, n))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            match cols {
+                Some(cols) if cols != row.len() => bail!(
+                    "line {}: expected {} numbers but found {}",
+                    line,
+                    cols,
+                    row.len()
+                ),
+                Some(_) => {}
+                None => cols = Some(row.len()),
+            }
+            for number in row {
+                if let Some(first) = seen.insert(number, *line) {
+                    if !allow_duplicates {
+                        bail!(
+                            "line {}: duplicate number {} (already on line {})",
+                            line,
+                            number,
+                            first
+                        );
+                    }
+                }
+                squares.push(Square::with_number(number));
+            }
+        }
+        Ok(Board {
+            rows: input.len(),
+            cols: cols.unwrap_or_default(),
+            squares,
+        })
+    }
+
+    fn score(&self) -> usize {
+        self.squares
+            .iter()
+            .filter(|s| !s.marked)
+            .map(|s| s.number)
+            .sum()
+    }
+}
+
+// marking a single board by scanning it, which `Game` does through its index instead, but which
+// is all the bingo server needs to check a claim
+impl Board {
+    fn draw(&mut self, draw_number: usize) {
+        for square in self.squares.iter_mut() {
+            if square.number == draw_number {
+                square.marked = true;
+            }
+        }
+    }
+
+    fn finished(&self, patterns: &[WinPattern]) -> bool {
+        self.winning_line(patterns).is_some()
+    }
+
+    fn winning_line(&self, patterns: &[WinPattern]) -> Option<Vec<usize>> {
+        patterns
+            .iter()
+            .flat_map(|pattern| pattern.lines(self.rows, self.cols))
+            .find(|line| line.iter().all(|idx| self.squares[*idx].marked))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct Square {
+    number: usize,
+    marked: bool,
+}
+
+impl Square {
+    const fn with_number(number: usize) -> Self {
+        Self {
+            number,
+            marked: false,
+        }
+    }
+}
+
+// the classic rules: any complete row or column wins
+const LINES: &[WinPattern] = &[WinPattern::Rows, WinPattern::Columns];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum WinPattern {
+    Rows,
+    Columns,
+    // only square boards have diagonals
+    Diagonals,
+    Corners,
+    // both diagonals at once
+    X,
+    Blackout,
+    Mask {
+        rows: usize,
+        cols: usize,
+        squares: Vec<usize>,
+    },
+}
+
+impl WinPattern {
+    // each line is a set of row-major square indices that wins once all of them are marked
+    fn lines(&self, rows: usize, cols: usize) -> Vec<Vec<usize>> {
+        let diagonals = || {
+            if rows == cols {
+                vec![
+                    (0..rows).map(|i| i * cols + i).collect::<Vec<_>>(),
+                    (0..rows)
+                        .map(|i| i * cols + cols - 1 - i)
+                        .collect::<Vec<_>>(),
+                ]
+            } else {
+                vec![]
+            }
+        };
+        match self {
+            WinPattern::Rows => (0..rows)
+                .map(|row| (0..cols).map(|col| row * cols + col).collect())
+                .collect(),
+            WinPattern::Columns => (0..cols)
+                .map(|col| (0..rows).map(|row| row * cols + col).collect())
+                .collect(),
+            WinPattern::Diagonals => diagonals(),
+            WinPattern::Corners => {
+                let mut corners = vec![0, cols - 1, (rows - 1) * cols, rows * cols - 1];
+                corners.sort_unstable();
+                corners.dedup();
+                vec![corners]
+            }
+            WinPattern::X => {
+                let mut squares = diagonals().concat();
+                if squares.is_empty() {
+                    return vec![];
+                }
+                squares.sort_unstable();
+                squares.dedup();
+                vec![squares]
+            }
+            WinPattern::Blackout => vec![(0..rows * cols).collect()],
+            WinPattern::Mask {
+                rows: mask_rows,
+                cols: mask_cols,
+                squares,
+            } => {
+                if (*mask_rows, *mask_cols) == (rows, cols) {
+                    vec![squares.clone()]
+                } else {
+                    vec![]
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for WinPattern {
+    type Err = Error;
+
+    // a mask is written as `mask:` followed by rows separated by `/`, with `#` for the squares
+    // that must be marked and `.` for the rest, e.g. `mask:#...#/...../..#../...../#...#`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rows" => Ok(WinPattern::Rows),
+            "columns" => Ok(WinPattern::Columns),
+            "diagonals" => Ok(WinPattern::Diagonals),
+            "corners" => Ok(WinPattern::Corners),
+            "x" => Ok(WinPattern::X),
+            "blackout" => Ok(WinPattern::Blackout),
+            _ => match s.strip_prefix("mask:") {
+                Some(mask) => {
+                    let mask_rows = mask.split('/').collect::<Vec<_>>();
+                    let cols = mask_rows[0].len();
+                    let mut squares = vec![];
+                    for (row, line) in mask_rows.iter().enumerate() {
+                        if line.len() != cols {
+                            bail!("Invalid mask {:?}: rows have different lengths", s);
+                        }
+                        for (col, c) in line.chars().enumerate() {
+                            match c {
+                                '#' => squares.push(row * cols + col),
+                                '.' => {}
+                                _ => bail!("Invalid mask {:?}: unexpected {:?}", s, c),
+                            }
+                        }
+                    }
+                    if squares.is_empty() {
+                        bail!("Invalid mask {:?}: no squares selected", s);
+                    }
+                    Ok(WinPattern::Mask {
+                        rows: mask_rows.len(),
+                        cols,
+                        squares,
+                    })
+                }
+                None => bail!("Invalid win pattern: {:?}", s),
+            },
+        }
+    }
+}
+
+impl Display for WinPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            WinPattern::Rows => write!(f, "rows"),
+            WinPattern::Columns => write!(f, "columns"),
+            WinPattern::Diagonals => write!(f, "diagonals"),
+            WinPattern::Corners => write!(f, "corners"),
+            WinPattern::X => write!(f, "x"),
+            WinPattern::Blackout => write!(f, "blackout"),
+            WinPattern::Mask {
+                rows,
+                cols,
+                squares,
+            } => {
+                let mask = (0..*rows)
+                    .map(|row| {
+                        (0..*cols)
+                            .map(|col| match squares.contains(&(row * cols + col)) {
+                                true => '#',
+                                false => '.',
+                            })
+                            .collect::<String>()
+                    })
+                    .join("/");
+                write!(f, "mask:{}", mask)
+            }
+        }
+    }
+}
+
+// a comma separated list of patterns, where `lines` is shorthand for `rows,columns`
+fn parse_patterns(s: &str) -> Result<Vec<WinPattern>, Error> {
+    let mut patterns = vec![];
+    for pattern in s.split(',').map(str::trim) {
+        match pattern {
+            "lines" => patterns.extend_from_slice(LINES),
+            _ => patterns.push(pattern.parse()?),
+        }
+    }
+    Ok(patterns)
+}
+
+fn check_patterns(patterns: &[WinPattern], boards: &[Board]) -> Result<(), Error> {
+    for pattern in patterns {
+        if let WinPattern::Mask { rows, cols, .. } = pattern {
+            if let Some(board) = boards
+                .iter()
+                .find(|board| (board.rows, board.cols) != (*rows, *cols))
+            {
+                bail!(
+                    "a {}x{} mask can't be used with a {}x{} board",
+                    rows,
+                    cols,
+                    board.rows,
+                    board.cols
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// the pattern lines shared by every board of one size, and for each square the lines it is on
+#[derive(Debug, Clone)]
+struct Layout {
+    rows: usize,
+    cols: usize,
+    lines: Vec<Vec<usize>>,
+    square_lines: Vec<Vec<usize>>,
+}
+
+impl Layout {
+    fn new(rows: usize, cols: usize, patterns: &[WinPattern]) -> Self {
+        let lines = patterns
+            .iter()
+            .flat_map(|pattern| pattern.lines(rows, cols))
+            .collect::<Vec<_>>();
+        let mut square_lines = vec![vec![]; rows * cols];
+        for (line_idx, line) in lines.iter().enumerate() {
+            for square in line {
+                square_lines[*square].push(line_idx);
+            }
+        }
+        Self {
+            rows,
+            cols,
+            lines,
+            square_lines,
+        }
+    }
+}
+
+// marks boards through an index from each number to the squares holding it, and counts the
+// marked squares on every pattern line so that a win is detected without rescanning the board
+#[derive(Debug, Clone)]
+struct Game {
+    boards: Vec<Board>,
+    layouts: Vec<Layout>,
+    board_layout: Vec<usize>,
+    index: HashMap<usize, Vec<(usize, usize)>>,
+    line_marks: Vec<Vec<usize>>,
+    unmarked: Vec<usize>,
+    // the first completed line of each board that has won
+    won: Vec<Option<usize>>,
+}
+
+impl Game {
+    fn new(boards: &[Board], patterns: &[WinPattern]) -> Self {
+        let mut layouts = Vec::<Layout>::new();
+        let mut board_layout = Vec::with_capacity(boards.len());
+        let mut index = HashMap::<usize, Vec<(usize, usize)>>::new();
+        for (board_idx, board) in boards.iter().enumerate() {
+            let layout = match layouts
+                .iter()
+                .position(|l| (l.rows, l.cols) == (board.rows, board.cols))
+            {
+                Some(layout) => layout,
+                None => {
+                    layouts.push(Layout::new(board.rows, board.cols, patterns));
+                    layouts.len() - 1
+                }
+            };
+            board_layout.push(layout);
+            for (square_idx, square) in board.squares.iter().enumerate() {
+                index
+                    .entry(square.number)
+                    .or_default()
+                    .push((board_idx, square_idx));
+            }
+        }
+        Self {
+            boards: boards.to_vec(),
+            line_marks: board_layout
+                .iter()
+                .map(|layout| vec![0; layouts[*layout].lines.len()])
+                .collect(),
+            unmarked: boards.iter().map(Board::score).collect(),
+            won: vec![None; boards.len()],
+            layouts,
+            board_layout,
+            index,
+        }
+    }
+
+    // returns the boards that won with this number, in board order; only the lines through the
+    // squares it marks are checked, as no other line can have been completed
+    fn draw(&mut self, draw_number: usize) -> Vec<usize> {
+        let Game {
+            boards,
+            layouts,
+            board_layout,
+            index,
+            line_marks,
+            unmarked,
+            won,
+        } = self;
+        let squares = match index.get(&draw_number) {
+            Some(squares) => squares,
+            None => return vec![],
+        };
+        let mut completed = vec![];
+        for &(board, square) in squares {
+            let marked = &mut boards[board].squares[square].marked;
+            if *marked {
+                continue;
+            }
+            *marked = true;
+            unmarked[board] -= draw_number;
+            let layout = &layouts[board_layout[board]];
+            for &line in &layout.square_lines[square] {
+                line_marks[board][line] += 1;
+                if won[board].is_none() && line_marks[board][line] == layout.lines[line].len() {
+                    completed.push((board, line));
+                }
+            }
+        }
+        // a board completing several lines at once won with the first of them
+        completed.sort_unstable();
+        let mut winners = vec![];
+        for (board, line) in completed {
+            if won[board].is_none() {
+                won[board] = Some(line);
+                winners.push(board);
+            }
+        }
+        winners
+    }
+
+    fn score(&self, board: usize) -> usize {
+        self.unmarked[board]
+    }
+
+    fn winning_line(&self, board: usize) -> Option<&[usize]> {
+        let layout = &self.layouts[self.board_layout[board]];
+        self.won[board].map(|line| layout.lines[line].as_slice())
+    }
+}
+
+fn play_bingo(boards: &[Board], draw_numbers: &[usize], patterns: &[WinPattern]) -> usize {
+    let mut game = Game::new(boards, patterns);
+    for draw_number in draw_numbers {
+        if let Some(board) = game.draw(*draw_number).first() {
+            return game.score(*board) * draw_number;
+        }
+    }
+    0
+}
+
+fn play_bingo_to_lose(boards: &[Board], draw_numbers: &[usize], patterns: &[WinPattern]) -> usize {
+    let mut game = Game::new(boards, patterns);
+    let mut remaining = boards.len();
+    for draw_number in draw_numbers {
+        let winners = game.draw(*draw_number);
+        if remaining == 1 && winners.len() == 1 {
+            return game.score(winners[0]) * draw_number;
+        }
+        remaining -= winners.len();
+    }
+    0
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct BoardResult {
+    board: usize,
+    // index into the draw numbers, `None` if the board never wins
+    won_at: Option<usize>,
+    winning_number: Option<usize>,
+    score: Option<usize>,
+    // row-major square indices of the first completed pattern line
+    winning_line: Option<Vec<usize>>,
+}
+
+// plays every draw and reports, in board order, when and how each board first won
+fn play_all(boards: &[Board], draw_numbers: &[usize], patterns: &[WinPattern]) -> Vec<BoardResult> {
+    let mut results = (0..boards.len())
+        .map(|board| BoardResult {
+            board,
+            won_at: None,
+            winning_number: None,
+            score: None,
+            winning_line: None,
+        })
+        .collect::<Vec<_>>();
+    let mut game = Game::new(boards, patterns);
+    let mut remaining = boards.len();
+    for (idx, draw_number) in draw_numbers.iter().enumerate() {
+        if remaining == 0 {
+            break;
+        }
+        for board in game.draw(*draw_number) {
+            remaining -= 1;
+            let result = &mut results[board];
+            result.won_at = Some(idx);
+            result.winning_number = Some(*draw_number);
+            result.score = Some(game.score(board) * draw_number);
+            result.winning_line = game.winning_line(board).map(<[usize]>::to_vec);
+        }
+    }
+    results
+}
+
+// boards that win on the same draw share a rank, boards that never win are listed last
+fn render_results(boards: &[Board], results: &[BoardResult]) -> String {
+    let mut ranked = results.iter().collect::<Vec<_>>();
+    ranked.sort_by_key(|result| (result.won_at.is_none(), result.won_at, result.board));
+    let mut out = String::from("rank  board   draw  number   score  line\n");
+    let mut rank = 0;
+    for (idx, result) in ranked.iter().enumerate() {
+        if idx == 0 || ranked[idx - 1].won_at != result.won_at {
+            rank = idx + 1;
+        }
+        match (
+            result.won_at,
+            result.winning_number,
+            result.score,
+            &result.winning_line,
+        ) {
+            (Some(won_at), Some(number), Some(score), Some(line)) => {
+                let line = line
+                    .iter()
+                    .map(|idx| boards[result.board].squares[*idx].number.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                out.push_str(&format!(
+                    "{:>4}  {:>5}  {:>5}  {:>6}  {:>6}  {}\n",
+                    rank,
+                    result.board + 1,
+                    won_at + 1,
+                    number,
+                    score,
+                    line
+                ));
+            }
+            _ => out.push_str(&format!(
+                "{:>4}  {:>5}  {:>5}  {:>6}  {:>6}  -\n",
+                "-",
+                result.board + 1,
+                "-",
+                "-",
+                "-"
+            )),
+        }
+    }
+    out
+}
+
+// splitmix64, so that simulations are reproducible from a seed without any dependencies
+#[derive(Debug, Clone)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in 0..n, rejecting the values that would bias the modulo
+    fn below(&mut self, n: usize) -> usize {
+        let n = n as u64;
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return (value % n) as usize;
+            }
+        }
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for idx in (1..items.len()).rev() {
+            items.swap(idx, self.below(idx + 1));
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct BoardOdds {
+    // boards that win on the same draw share the credit for winning first (or last)
+    wins_first: f64,
+    // a board that never wins counts as finishing last
+    wins_last: f64,
+    // how many trials the board needed exactly `n` draws to win, indexed by `n`
+    draws_needed: Vec<usize>,
+    never_won: usize,
+}
+
+impl BoardOdds {
+    // the smallest number of draws within which the board won in at least `fraction` of the trials
+    fn percentile(&self, fraction: f64) -> Option<usize> {
+        let won = self.draws_needed.iter().sum::<usize>();
+        if won == 0 {
+            return None;
+        }
+        let target = (fraction * won as f64).ceil().max(1.0) as usize;
+        let mut seen = 0;
+        self.draws_needed.iter().position(|count| {
+            seen += count;
+            seen >= target
+        })
+    }
+
+    fn mean_draws(&self) -> Option<f64> {
+        let won = self.draws_needed.iter().sum::<usize>();
+        let total = self
+            .draws_needed
+            .iter()
+            .enumerate()
+            .map(|(draws, count)| draws * count)
+            .sum::<usize>();
+        (won > 0).then(|| total as f64 / won as f64)
+    }
+}
+
+// plays `trials` games, each drawing every number of the pool in a random order
+fn simulate(
+    boards: &[Board],
+    pool: &[usize],
+    patterns: &[WinPattern],
+    trials: usize,
+    seed: u64,
+) -> Vec<BoardOdds> {
+    let mut odds = vec![
+        BoardOdds {
+            wins_first: 0.0,
+            wins_last: 0.0,
+            draws_needed: vec![0; pool.len() + 1],
+            never_won: 0,
+        };
+        boards.len()
+    ];
+    let mut rng = Rng::new(seed);
+    let template = Game::new(boards, patterns);
+    let mut pool = pool.to_vec();
+    for _ in 0..trials {
+        rng.shuffle(&mut pool);
+        let mut game = template.clone();
+        let mut won_at = vec![None; boards.len()];
+        let mut remaining = boards.len();
+        for (idx, draw_number) in pool.iter().enumerate() {
+            for board in game.draw(*draw_number) {
+                won_at[board] = Some(idx + 1);
+                remaining -= 1;
+            }
+            if remaining == 0 {
+                break;
+            }
+        }
+
+        let first = won_at.iter().flatten().min().copied();
+        let last = won_at.iter().map(|w| w.unwrap_or(usize::MAX)).max();
+        let firsts = won_at
+            .iter()
+            .filter(|w| first.is_some() && **w == first)
+            .count();
+        let lasts = won_at
+            .iter()
+            .filter(|w| Some(w.unwrap_or(usize::MAX)) == last)
+            .count();
+        for (odds, won_at) in odds.iter_mut().zip(&won_at) {
+            match won_at {
+                Some(draws) => odds.draws_needed[*draws] += 1,
+                None => odds.never_won += 1,
+            }
+            if first.is_some() && *won_at == first {
+                odds.wins_first += 1.0 / firsts as f64;
+            }
+            if Some(won_at.unwrap_or(usize::MAX)) == last {
+                odds.wins_last += 1.0 / lasts as f64;
+            }
+        }
+    }
+    for odds in odds.iter_mut() {
+        odds.wins_first /= trials.max(1) as f64;
+        odds.wins_last /= trials.max(1) as f64;
+    }
+    odds
+}
+
+fn render_odds(odds: &[BoardOdds], trials: usize) -> String {
+    let draws = |draws: Option<usize>| draws.map_or("-".to_string(), |d| d.to_string());
+    let mut out = format!("{} trials\n", trials);
+    out.push_str("board   first    last    mean   min   p10   p50   p90   max   never\n");
+    for (board, odds) in odds.iter().enumerate() {
+        out.push_str(&format!(
+            "{:>5}  {:>6.2}%  {:>5.2}%  {:>6}  {:>4}  {:>4}  {:>4}  {:>4}  {:>4}  {:>6}\n",
+            board + 1,
+            odds.wins_first * 100.0,
+            odds.wins_last * 100.0,
+            odds.mean_draws()
+                .map_or("-".to_string(), |mean| format!("{:.1}", mean)),
+            draws(odds.draws_needed.iter().position(|count| *count > 0)),
+            draws(odds.percentile(0.1)),
+            draws(odds.percentile(0.5)),
+            draws(odds.percentile(0.9)),
+            draws(odds.draws_needed.iter().rposition(|count| *count > 0)),
+            odds.never_won
+        ));
+    }
+    out
+}
+
+fn board_index(board: usize, boards: &[Board]) -> Result<usize, Error> {
+    if board == 0 || board > boards.len() {
+        bail!(
+            "board {} doesn't exist, there are {} boards",
+            board,
+            boards.len()
+        );
+    }
+    Ok(board - 1)
+}
+
+// the largest number of candidate sets the exact win-last search checks before it falls back to
+// a greedy search
+const SEARCH_BUDGET: usize = 1_000_000;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct DrawOrder {
+    draws: Vec<usize>,
+    // the number of draws after which the board wins
+    wins_at: usize,
+    // whether the search was exhaustive rather than a greedy guess
+    exact: bool,
+}
+
+// no order was found; only an exact search proves that none exists
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Exhausted {
+    exact: bool,
+}
+
+// the distinct numbers of every pattern line of a board that can be completed from the pool
+fn line_numbers(board: &Board, pool: &HashSet<usize>, patterns: &[WinPattern]) -> Vec<Vec<usize>> {
+    let mut lines = patterns
+        .iter()
+        .flat_map(|pattern| pattern.lines(board.rows, board.cols))
+        .map(|line| {
+            line.iter()
+                .map(|idx| board.squares[*idx].number)
+                .sorted_unstable()
+                .dedup()
+                .collect::<Vec<_>>()
+        })
+        .filter(|line| line.iter().all(|n| pool.contains(n)))
+        .collect::<Vec<_>>();
+    lines.sort_unstable();
+    lines.dedup();
+    lines
+}
+
+// Drawing exactly the numbers of one of the board's lines is the shortest way for it to win, and
+// that only wins outright if the same numbers don't also complete a line on another board, so
+// checking every line of the board is an exact search.
+fn shortest_win_first(
+    boards: &[Board],
+    pool: &[usize],
+    patterns: &[WinPattern],
+    board: usize,
+) -> Option<DrawOrder> {
+    let pool_set = pool.iter().copied().collect::<HashSet<_>>();
+    let others = boards
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| *idx != board)
+        .flat_map(|(_, other)| line_numbers(other, &pool_set, patterns))
+        .collect::<Vec<_>>();
+    line_numbers(&boards[board], &pool_set, patterns)
+        .into_iter()
+        .filter(|line| {
+            let drawn = line.iter().collect::<HashSet<_>>();
+            !others
+                .iter()
+                .any(|other| other.iter().all(|n| drawn.contains(n)))
+        })
+        .min_by_key(Vec::len)
+        .map(|line| {
+            // in the order the numbers appear in the pool, for a stable answer
+            let draws = pool
+                .iter()
+                .filter(|n| line.contains(n))
+                .copied()
+                .unique()
+                .collect::<Vec<_>>();
+            DrawOrder {
+                wins_at: draws.len(),
+                draws,
+                exact: true,
+            }
+        })
+}
+
+// Every other board has to complete a line before the board does. Holding back a set of numbers
+// that touches every line of the board keeps it from winning until they are drawn, so this
+// searches for such a held back set that still leaves every other board a line it can complete.
+// Only minimal held back sets need checking, as holding back more only breaks more lines. After
+// `budget` candidate sets it falls back to a greedy search.
+fn order_to_win_last(
+    boards: &[Board],
+    pool: &[usize],
+    patterns: &[WinPattern],
+    board: usize,
+    budget: usize,
+) -> Result<DrawOrder, Exhausted> {
+    let pool_set = pool.iter().copied().collect::<HashSet<_>>();
+    let lines = line_numbers(&boards[board], &pool_set, patterns);
+    if lines.is_empty() {
+        return Err(Exhausted { exact: true });
+    }
+    let others = boards
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| *idx != board)
+        .map(|(_, other)| line_numbers(other, &pool_set, patterns))
+        .collect::<Vec<_>>();
+    let feasible = |held: &HashSet<usize>| {
+        lines
+            .iter()
+            .all(|line| line.iter().any(|n| held.contains(n)))
+            && others.iter().all(|other_lines| {
+                other_lines
+                    .iter()
+                    .any(|line| line.iter().all(|n| !held.contains(n)))
+            })
+    };
+
+    let candidates = lines
+        .iter()
+        .flatten()
+        .copied()
+        .unique()
+        .sorted()
+        .collect::<Vec<_>>();
+    let mut checked = 0;
+    let mut held = None;
+    let mut exact = true;
+    'search: for size in 1..=candidates.len() {
+        for combination in candidates.iter().copied().combinations(size) {
+            checked += 1;
+            if checked > budget {
+                exact = false;
+                break 'search;
+            }
+            let combination = combination.into_iter().collect::<HashSet<_>>();
+            if feasible(&combination) {
+                held = Some(combination);
+                break 'search;
+            }
+        }
+    }
+    if !exact {
+        held = Some(greedy_hitting_set(&lines, &others)).filter(|held| feasible(held));
+    }
+
+    let held = held.ok_or(Exhausted { exact })?;
+    let mut draws = pool
+        .iter()
+        .filter(|n| !held.contains(n))
+        .copied()
+        .collect::<Vec<_>>();
+    draws.extend(pool.iter().filter(|n| held.contains(n)));
+    let mut drawn = HashSet::new();
+    let wins_at = draws
+        .iter()
+        .position(|n| {
+            drawn.insert(*n);
+            lines
+                .iter()
+                .any(|line| line.iter().all(|n| drawn.contains(n)))
+        })
+        .map_or(draws.len(), |idx| idx + 1);
+    Ok(DrawOrder {
+        draws,
+        wins_at,
+        exact,
+    })
+}
+
+// repeatedly holds back the number that touches the most lines of the board not yet touched,
+// preferring numbers on the fewest lines of the other boards
+fn greedy_hitting_set(lines: &[Vec<usize>], others: &[Vec<Vec<usize>>]) -> HashSet<usize> {
+    let mut held = HashSet::new();
+    loop {
+        let untouched = lines
+            .iter()
+            .filter(|line| line.iter().all(|n| !held.contains(n)))
+            .collect::<Vec<_>>();
+        if untouched.is_empty() {
+            return held;
+        }
+        let best = untouched
+            .iter()
+            .flat_map(|line| line.iter())
+            .unique()
+            .min_by_key(|n| {
+                let touched = untouched.iter().filter(|line| line.contains(n)).count();
+                let damage = others
+                    .iter()
+                    .flatten()
+                    .filter(|line| line.contains(n))
+                    .count();
+                (usize::MAX - touched, damage, **n)
+            })
+            .copied();
+        match best {
+            Some(n) => {
+                held.insert(n);
+            }
+            None => return held,
+        }
+    }
+}
+
+// how many times a board is regenerated before giving up on finding one unlike the others
+const GENERATOR_ATTEMPTS: usize = 1_000;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Generator {
+    rows: usize,
+    cols: usize,
+    // inclusive
+    min: usize,
+    max: usize,
+    unique_squares: bool,
+    unique_boards: bool,
+    // split the range evenly between the columns, like the B-I-N-G-O columns of a real card
+    column_ranges: bool,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self {
+            rows: 5,
+            cols: 5,
+            min: 0,
+            max: 99,
+            unique_squares: true,
+            unique_boards: true,
+            column_ranges: false,
+        }
+    }
+}
+
+impl Generator {
+    // the inclusive range of numbers each column is drawn from
+    fn column_range(&self, col: usize) -> (usize, usize) {
+        if !self.column_ranges {
+            return (self.min, self.max);
+        }
+        let len = self.max - self.min + 1;
+        (
+            self.min + col * len / self.cols,
+            self.min + (col + 1) * len / self.cols - 1,
+        )
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.rows == 0 || self.cols == 0 {
+            bail!("boards need at least one row and column");
+        }
+        if self.min > self.max {
+            bail!("empty range {}-{}", self.min, self.max);
+        }
+        let len = self.max - self.min + 1;
+        if self.column_ranges && len < self.cols {
+            bail!("can't split {} numbers between {} columns", len, self.cols);
+        }
+        if self.unique_squares {
+            let needed = if self.column_ranges {
+                self.rows
+            } else {
+                self.rows * self.cols
+            };
+            let (min, max) = self.column_range(0);
+            if max - min + 1 < needed {
+                bail!(
+                    "not enough numbers in {}-{} for {} different squares",
+                    min,
+                    max,
+                    needed
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn generate_board(&self, rng: &mut Rng) -> Board {
+        let mut squares = vec![Square::default(); self.rows * self.cols];
+        let mut used = HashSet::new();
+        for col in 0..self.cols {
+            let (min, max) = self.column_range(col);
+            for row in 0..self.rows {
+                let number = loop {
+                    let number = min + rng.below(max - min + 1);
+                    if !self.unique_squares || used.insert(number) {
+                        break number;
+                    }
+                };
+                squares[row * self.cols + col] = Square::with_number(number);
+            }
+        }
+        Board {
+            rows: self.rows,
+            cols: self.cols,
+            squares,
+        }
+    }
+
+    fn generate(&self, boards: usize, rng: &mut Rng) -> Result<Vec<Board>, Error> {
+        self.check()?;
+        let mut seen = HashSet::new();
+        let mut generated = Vec::with_capacity(boards);
+        for _ in 0..boards {
+            let board = (0..GENERATOR_ATTEMPTS)
+                .map(|_| self.generate_board(rng))
+                .find(|board| {
+                    !self.unique_boards
+                        || seen.insert(board.squares.iter().map(|s| s.number).collect::<Vec<_>>())
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "couldn't find a board unlike the {} already generated",
+                        generated.len()
+                    )
+                })?;
+            generated.push(board);
+        }
+        Ok(generated)
+    }
+
+    // a shuffled draw of the whole range followed by the boards, as `parse_game` reads them
+    fn render_game(&self, boards: &[Board], rng: &mut Rng) -> String {
+        let mut draw_numbers = (self.min..=self.max).collect::<Vec<_>>();
+        rng.shuffle(&mut draw_numbers);
+        let mut out = draw_numbers.iter().map(usize::to_string).join(",");
+        out.push('\n');
+        for board in boards {
+            out.push('\n');
+            out.push_str(&render_board(board));
+        }
+        out
+    }
+}
+
+fn render_board(board: &Board) -> String {
+    let width = board
+        .squares
+        .iter()
+        .map(|s| s.number.to_string().len())
+        .max()
+        .unwrap_or_default();
+    board
+        .squares
+        .chunks(board.cols)
+        .map(|row| {
+            row.iter()
+                .map(|s| format!("{:>width$}", s.number, width = width))
+                .join(" ")
+                + "\n"
+        })
+        .collect()
+}
+
+// the time between draws when replaying or serving a game
+const DEFAULT_SPEED: Duration = Duration::from_millis(500);
+// boards are laid out side by side, wrapping at this many columns
+const REPLAY_WIDTH: usize = 100;
+// a connection that hasn't joined after this long is dropped, so it can't hold up the others
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Style {
+    Ansi,
+    // marked squares get a trailing '*' and winning lines a trailing '#'
+    Plain,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum ReplayCommand {
+    Step,
+    // play to the end, optionally at a different speed
+    Play(Option<Duration>),
+    // move to just after the given number of draws
+    Jump(usize),
+    // take back this many draws
+    Undo(usize),
+    Save(String),
+    Quit,
+}
+
+impl FromStr for ReplayCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (None | Some("n" | "next"), None) => ReplayCommand::Step,
+            (Some("p" | "play"), None) => ReplayCommand::Play(None),
+            (Some("p" | "play"), Some(ms)) => {
+                ReplayCommand::Play(Some(Duration::from_millis(ms.parse()?)))
+            }
+            (Some("j" | "jump"), Some(draw)) => ReplayCommand::Jump(draw.parse()?),
+            (Some("u" | "undo"), None) => ReplayCommand::Undo(1),
+            (Some("u" | "undo"), Some(draws)) => ReplayCommand::Undo(draws.parse()?),
+            (Some("s" | "save"), Some(path)) => ReplayCommand::Save(path.to_string()),
+            (Some("q" | "quit"), None) => ReplayCommand::Quit,
+            _ => bail!("Unknown command: {:?}", s.trim()),
+        };
+        if words.next().is_some() {
+            bail!("Unknown command: {:?}", s.trim());
+        }
+        Ok(command)
+    }
+}
+
+struct Replay<'a> {
+    boards: &'a [Board],
+    draw_numbers: &'a [usize],
+    patterns: &'a [WinPattern],
+    game: Game,
+    drawn: usize,
+    // boards that won on the latest draw
+    just_won: Vec<usize>,
+    // the number of draws after which each board first won
+    won_at: Vec<Option<usize>>,
+}
+
+impl<'a> Replay<'a> {
+    fn new(boards: &'a [Board], draw_numbers: &'a [usize], patterns: &'a [WinPattern]) -> Self {
+        Self {
+            boards,
+            draw_numbers,
+            patterns,
+            game: Game::new(boards, patterns),
+            drawn: 0,
+            just_won: vec![],
+            won_at: vec![None; boards.len()],
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.drawn == self.draw_numbers.len()
+    }
+
+    fn step(&mut self) {
+        if let Some(draw_number) = self.draw_numbers.get(self.drawn) {
+            self.just_won = self.game.draw(*draw_number);
+            self.drawn += 1;
+            for board in &self.just_won {
+                self.won_at[*board] = Some(self.drawn);
+            }
+        }
+    }
+
+    // going backwards replays from the start, which is cheap next to the rendering
+    fn jump(&mut self, draw: usize) {
+        let draw = draw.min(self.draw_numbers.len());
+        if draw < self.drawn {
+            *self = Replay::new(self.boards, self.draw_numbers, self.patterns);
+        }
+        while self.drawn < draw {
+            self.step();
+        }
+    }
+
+    fn undo(&mut self, draws: usize) {
+        self.jump(self.drawn.saturating_sub(draws));
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            draw_numbers: self.draw_numbers.to_vec(),
+            patterns: self.patterns.to_vec(),
+            drawn: self.drawn,
+            won_at: self.won_at.clone(),
+            boards: self.game.boards.clone(),
+        }
+    }
+
+    fn render(&self, style: Style) -> String {
+        let mut out = match self.drawn.checked_sub(1) {
+            Some(draw) => format!(
+                "Draw {} of {}: {}\n",
+                self.drawn,
+                self.draw_numbers.len(),
+                self.draw_numbers[draw]
+            ),
+            None => format!("Draw 0 of {}\n", self.draw_numbers.len()),
+        };
+        let won = self.game.won.iter().filter(|w| w.is_some()).count();
+        out += &format!("{} of {} boards have won", won, self.boards.len());
+        if !self.just_won.is_empty() {
+            out += &format!(
+                ", just won: {}",
+                self.just_won.iter().map(|b| b + 1).join(", ")
+            );
+        }
+        out += "\n";
+
+        let width = self
+            .boards
+            .iter()
+            .flat_map(|b| &b.squares)
+            .map(|s| s.number.to_string().len())
+            .max()
+            .unwrap_or_default();
+        let blocks = self
+            .game
+            .boards
+            .iter()
+            .enumerate()
+            .map(|(idx, board)| self.render_board(idx, board, width, style))
+            .collect::<Vec<_>>();
+        let block_width = blocks
+            .iter()
+            .flatten()
+            .map(|(_, len)| *len)
+            .max()
+            .unwrap_or_default();
+        let per_row = (REPLAY_WIDTH / (block_width + 3)).max(1);
+        for row in blocks.chunks(per_row) {
+            out += "\n";
+            let height = row.iter().map(Vec::len).max().unwrap_or_default();
+            for line in 0..height {
+                let text = row
+                    .iter()
+                    .map(|block| match block.get(line) {
+                        // padded by hand since escape codes don't take up any width
+                        Some((text, len)) => format!("{}{}", text, " ".repeat(block_width - len)),
+                        None => " ".repeat(block_width),
+                    })
+                    .join("   ");
+                out += text.trim_end();
+                out += "\n";
+            }
+        }
+        out
+    }
+
+    // the lines of one board with their visible widths
+    fn render_board(
+        &self,
+        idx: usize,
+        board: &Board,
+        width: usize,
+        style: Style,
+    ) -> Vec<(String, usize)> {
+        let just_won = self.just_won.contains(&idx);
+        let title = match (self.game.won[idx].is_some(), just_won) {
+            (_, true) => format!("Board {} BINGO!", idx + 1),
+            (true, false) => format!("Board {} won", idx + 1),
+            (false, false) => format!("Board {}", idx + 1),
+        };
+        let title = match (style, just_won) {
+            (Style::Ansi, true) => (format!("\x1b[1;33m{}\x1b[0m", title), title.len()),
+            _ => (title.clone(), title.len()),
+        };
+        let line = self.game.winning_line(idx).unwrap_or_default();
+        let current = self.drawn.checked_sub(1).map(|d| self.draw_numbers[d]);
+        let mut lines = vec![title];
+        for (row_idx, row) in board.squares.chunks(board.cols).enumerate() {
+            let mut squares = row.iter().enumerate().map(|(col, square)| {
+                let number = format!("{:>width$}", square.number, width = width);
+                let on_line = line.contains(&(row_idx * board.cols + col));
+                match style {
+                    Style::Plain => match (on_line, square.marked) {
+                        (true, _) => number + "#",
+                        (false, true) => number + "*",
+                        (false, false) => number + " ",
+                    },
+                    Style::Ansi => {
+                        let code = if on_line {
+                            "1;32;7"
+                        } else if square.marked && Some(square.number) == current {
+                            "1;33;7"
+                        } else if square.marked {
+                            "7"
+                        } else {
+                            ""
+                        };
+                        if code.is_empty() {
+                            number + " "
+                        } else {
+                            format!("\x1b[{}m{}\x1b[0m ", code, number)
+                        }
+                    }
+                }
+            });
+            lines.push((squares.join(" "), board.cols * (width + 2) - 1));
+        }
+        lines
+    }
+
+    fn run(
+        &mut self,
+        input: impl BufRead,
+        out: &mut impl Write,
+        speed: Duration,
+        style: Style,
+    ) -> Result<(), Error> {
+        let clear = if style == Style::Ansi {
+            "\x1b[2J\x1b[H"
+        } else {
+            ""
+        };
+        let help = "[enter] step, p [MS] play, j N jump to draw N, u [K] undo, s PATH save, q quit";
+        write!(out, "{}{}{}\n> ", clear, self.render(style), help)?;
+        out.flush()?;
+        for line in input.lines() {
+            let command = match line?.parse::<ReplayCommand>() {
+                Ok(command) => command,
+                Err(e) => {
+                    write!(out, "{}\n{}\n> ", e, help)?;
+                    out.flush()?;
+                    continue;
+                }
+            };
+            match command {
+                ReplayCommand::Step => self.step(),
+                ReplayCommand::Play(delay) => {
+                    while !self.finished() {
+                        self.step();
+                        write!(out, "{}{}", clear, self.render(style))?;
+                        out.flush()?;
+                        std::thread::sleep(delay.unwrap_or(speed));
+                    }
+                }
+                ReplayCommand::Jump(draw) => self.jump(draw),
+                ReplayCommand::Undo(draws) => self.undo(draws),
+                ReplayCommand::Save(path) => {
+                    let status = match save_snapshot(self, &path) {
+                        Ok(()) => format!("Saved draw {} to {}", self.drawn, path),
+                        Err(e) => format!("{:#}", e),
+                    };
+                    write!(out, "{}\n{}\n> ", status, help)?;
+                    out.flush()?;
+                    continue;
+                }
+                ReplayCommand::Quit => return Ok(()),
+            }
+            write!(out, "{}{}{}\n> ", clear, self.render(style), help)?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// a game paused after some draws, saved as a header of `key value` lines followed by the boards
+// as they were marked, e.g.
+//
+//   draws 7,4,9,5,11,17,23,2,0,14,21,24,10,16
+//   drawn 12
+//   win rows,columns
+//   won 3@12
+//
+//   22 13 17* 11*  0*
+//   ...
+//
+// restoring replays the draws on unmarked boards and checks they end up in the saved state, so a
+// snapshot can't claim marks or wins that its draws don't explain
+#[derive(Debug, PartialEq, Clone)]
+struct Snapshot {
+    draw_numbers: Vec<usize>,
+    patterns: Vec<WinPattern>,
+    drawn: usize,
+    // the number of draws after which each board first won
+    won_at: Vec<Option<usize>>,
+    boards: Vec<Board>,
+}
+
+impl Snapshot {
+    fn initial_boards(&self) -> Vec<Board> {
+        let mut boards = self.boards.clone();
+        for square in boards.iter_mut().flat_map(|b| &mut b.squares) {
+            square.marked = false;
+        }
+        boards
+    }
+
+    fn restore<'a>(&'a self, boards: &'a [Board]) -> Result<Replay<'a>, Error> {
+        check_patterns(&self.patterns, boards)?;
+        let mut replay = Replay::new(boards, &self.draw_numbers, &self.patterns);
+        replay.jump(self.drawn);
+        for (idx, (saved, replayed)) in self.boards.iter().zip(&replay.game.boards).enumerate() {
+            if saved != replayed {
+                bail!(
+                    "board {} isn't marked the way the first {} draws mark it",
+                    idx + 1,
+                    self.drawn
+                );
+            }
+            if self.won_at[idx] != replay.won_at[idx] {
+                bail!(
+                    "board {} is saved as winning at draw {} but the draws have it win at {}",
+                    idx + 1,
+                    render_won_at(self.won_at[idx]),
+                    render_won_at(replay.won_at[idx])
+                );
+            }
+        }
+        Ok(replay)
+    }
+}
+
+fn render_won_at(won_at: Option<usize>) -> String {
+    won_at.map_or_else(|| "never".to_string(), |draw| draw.to_string())
+}
+
+impl Display for Snapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "draws {}", self.draw_numbers.iter().join(","))?;
+        writeln!(f, "drawn {}", self.drawn)?;
+        writeln!(f, "win {}", self.patterns.iter().join(","))?;
+        let won = self
+            .won_at
+            .iter()
+            .enumerate()
+            .filter_map(|(board, draw)| draw.map(|draw| format!("{}@{}", board + 1, draw)))
+            .join(",");
+        writeln!(f, "won {}", won)?;
+        for board in &self.boards {
+            writeln!(f)?;
+            let width = board
+                .squares
+                .iter()
+                .map(|s| s.number.to_string().len())
+                .max()
+                .unwrap_or_default();
+            for row in board.squares.chunks(board.cols) {
+                let row = row
+                    .iter()
+                    .map(|s| {
+                        let mark = if s.marked { "*" } else { " " };
+                        format!("{:>width$}{}", s.number, mark, width = width)
+                    })
+                    .join(" ");
+                writeln!(f, "{}", row.trim_end())?;
+            }
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-struct Board<'a> {
-    squares: [[Square<'a>; BOARD_SIZE]; BOARD_SIZE],
-}
+impl FromStr for Snapshot {
+    type Err = Error;
 
-impl<'a> Board<'a> {
-    fn parse(input: &[&'a str]) -> Self {
-        assert!(input.len() == BOARD_SIZE);
-        let mut this = Board {
-            squares: Default::default(),
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines = s
+            .split('\n')
+            .map(str::trim)
+            .enumerate()
+            .map(|(idx, line)| (idx + 1, line))
+            .collect::<Vec<_>>();
+        let mut groups = lines.split(|(_, line)| line.is_empty());
+        let mut header = HashMap::new();
+        for (line, text) in groups.next().unwrap_or_default() {
+            let (key, value) = text.split_once(' ').unwrap_or((text, ""));
+            header.insert(key, (*line, value.trim()));
+        }
+        let field = |key: &str| {
+            header
+                .get(key)
+                .copied()
+                .ok_or_else(|| anyhow!("missing {:?} line", key))
         };
-        for (row, input) in input.iter().enumerate() {
-            for (col, input) in input
-                .split(' ')
-                .map(str::trim)
-                .filter(|s| !s.is_empty())
-                .enumerate()
-            {
-                this.squares[row][col].number = input;
+
+        let (line, draws) = field("draws")?;
+        let draw_numbers = draws
+            .split(',')
+            .map(|n| {
+                n.parse()
+                    .map_err(|_| anyhow!("line {}: invalid draw number {:?}", line, n))
+            })
+            .collect::<Result<Vec<usize>, _>>()?;
+        let (line, drawn) = field("drawn")?;
+        let drawn = drawn
+            .parse::<usize>()
+            .map_err(|_| anyhow!("line {}: invalid draw count {:?}", line, drawn))?;
+        if drawn > draw_numbers.len() {
+            bail!(
+                "line {}: {} draws made but only {} to make",
+                line,
+                drawn,
+                draw_numbers.len()
+            );
+        }
+        let (line, patterns) = field("win")?;
+        let patterns = parse_patterns(patterns).with_context(|| format!("line {}", line))?;
+
+        let mut boards = vec![];
+        for group in groups.filter(|group| !group.is_empty()) {
+            let numbers = group
+                .iter()
+                .map(|(line, text)| (*line, text.replace('*', " ")))
+                .collect::<Vec<_>>();
+            let numbers = numbers
+                .iter()
+                .map(|(line, text)| (*line, text.as_str()))
+                .collect::<Vec<_>>();
+            let mut board = Board::parse_numbered(&numbers, true)?;
+            let marks = group.iter().flat_map(|(_, text)| {
+                text.split_whitespace()
+                    .flat_map(|token| token.split_inclusive('*'))
+                    .map(|token| token.ends_with('*'))
+            });
+            for (square, marked) in board.squares.iter_mut().zip(marks) {
+                square.marked = marked;
             }
+            boards.push(board);
         }
-        this
-    }
 
-    fn draw(&mut self, draw_number: &str) {
-        for row in self.squares.iter_mut() {
-            for col in row {
-                if col.number == draw_number {
-                    col.marked = true;
-                }
+        let (line, won) = field("won")?;
+        let mut won_at = vec![None; boards.len()];
+        for entry in won.split(',').filter(|entry| !entry.is_empty()) {
+            let (board, draw) = entry
+                .split_once('@')
+                .and_then(|(board, draw)| Some((board.parse::<usize>().ok()?, draw.parse().ok()?)))
+                .ok_or_else(|| anyhow!("line {}: invalid win {:?}", line, entry))?;
+            match won_at.get_mut(board.wrapping_sub(1)) {
+                Some(won_at) => *won_at = Some(draw),
+                None => bail!("line {}: no board {}", line, board),
             }
         }
+
+        Ok(Snapshot {
+            draw_numbers,
+            patterns,
+            drawn,
+            won_at,
+            boards,
+        })
     }
+}
 
-    fn finished(&self) -> bool {
-        let mut column_results = [true; BOARD_SIZE];
-        for row in self.squares {
-            if row.iter().all(|s| s.marked) {
-                return true;
-            }
-            for (col_num, col) in row.iter().enumerate() {
-                if !col.marked {
-                    column_results[col_num] = false;
-                }
-            }
+fn save_snapshot(replay: &Replay, path: &str) -> Result<(), Error> {
+    std::fs::write(path, replay.snapshot().to_string()).with_context(|| format!("writing {}", path))
+}
+
+// the bingo server's line protocol, one message per line:
+//   client: JOIN <name> | BINGO | QUIT
+//   server: WELCOME <player> | BOARD <rows>x<cols> <n,n,...> | DRAW <n> | REJECTED <reason>
+//           | WINNER <name> <score> | END | ERROR <reason>
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum ClientMessage {
+    Join(String),
+    Bingo,
+    Quit,
+}
+
+impl FromStr for ClientMessage {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("JOIN"), Some(name), None) => Ok(ClientMessage::Join(name.to_string())),
+            (Some("BINGO"), None, None) => Ok(ClientMessage::Bingo),
+            (Some("QUIT"), None, None) => Ok(ClientMessage::Quit),
+            _ => bail!("unknown message {:?}", s.trim()),
         }
-        column_results.iter().any(|c| *c)
     }
+}
 
-    fn score(&self) -> usize {
-        let mut score = 0;
-        for row in self.squares {
-            for col in row {
-                if !col.marked {
-                    score += col.number.parse::<usize>().unwrap_or_default();
-                }
-            }
+// what a player's connection thread passes on to the game
+enum ClientEvent {
+    Claim(usize),
+    Invalid(usize, String),
+    Left(usize),
+}
+
+struct Player {
+    name: String,
+    board: Board,
+    stream: TcpStream,
+    connected: bool,
+}
+
+impl Player {
+    // a player whose connection fails is dropped from the game rather than ending it
+    fn send(&mut self, message: &str) {
+        if self.connected && writeln!(self.stream, "{}", message).is_err() {
+            self.connected = false;
         }
-        score
     }
 }
 
-#[derive(Debug, Default, PartialEq, Clone, Copy)]
-struct Square<'a> {
-    number: &'a str,
-    marked: bool,
+fn render_board_message(board: &Board) -> String {
+    format!(
+        "BOARD {}x{} {}",
+        board.rows,
+        board.cols,
+        board.squares.iter().map(|s| s.number).join(",")
+    )
 }
 
-#[cfg(test)]
-impl<'a> Square<'a> {
-    const fn with_number(number: &'a str) -> Self {
-        Self {
-            number,
-            marked: false,
+// waits for `count` players to join, turning away connections that don't start with JOIN
+fn accept_players(
+    listener: &TcpListener,
+    count: usize,
+    timeout: Duration,
+) -> Result<Vec<(String, TcpStream, BufReader<TcpStream>)>, Error> {
+    let mut joined = vec![];
+    while joined.len() < count {
+        let (mut stream, _) = listener.accept()?;
+        stream.set_read_timeout(Some(timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() {
+            let _ = writeln!(stream, "ERROR timed out waiting for JOIN <name>");
+            continue;
+        }
+        match line.parse::<ClientMessage>() {
+            Ok(ClientMessage::Join(name)) => {
+                // the game itself waits on players for as long as it takes
+                stream.set_read_timeout(None)?;
+                writeln!(stream, "WELCOME {}", joined.len() + 1)?;
+                joined.push((name, stream, reader));
+            }
+            _ => {
+                let _ = writeln!(stream, "ERROR expected JOIN <name>");
+            }
         }
     }
+    Ok(joined)
 }
 
-fn play_bingo(boards: &[Board], draw_numbers: &[&str]) -> usize {
-    let mut boards = boards.to_vec();
-    for draw_number in draw_numbers {
-        boards.iter_mut().for_each(|board| board.draw(draw_number));
-        if let Some(board) = boards.iter().find(|b| b.finished()) {
-            return board.score() * draw_number.parse::<usize>().unwrap_or_default();
+fn read_client(player: usize, reader: BufReader<TcpStream>, events: mpsc::Sender<ClientEvent>) {
+    for line in reader.lines() {
+        let event = match line.map(|line| line.parse::<ClientMessage>()) {
+            Ok(Ok(ClientMessage::Bingo)) => ClientEvent::Claim(player),
+            Ok(Ok(ClientMessage::Join(_))) => {
+                ClientEvent::Invalid(player, "already joined".to_string())
+            }
+            Ok(Err(e)) => ClientEvent::Invalid(player, e.to_string()),
+            Ok(Ok(ClientMessage::Quit)) | Err(_) => break,
+        };
+        if events.send(event).is_err() {
+            return;
         }
     }
-    0
+    let _ = events.send(ClientEvent::Left(player));
 }
 
-fn play_bingo_to_lose(boards: &[Board], draw_numbers: &[&str]) -> usize {
-    let mut boards = boards.to_vec();
-    for draw_number in draw_numbers {
-        boards.iter_mut().for_each(|board| board.draw(draw_number));
-        if boards.len() == 1 && boards[0].finished() {
-            return boards[0].score() * draw_number.parse::<usize>().unwrap_or_default();
+// deals every player a generated board and draws the whole range in a random order, one number
+// every `speed`, until someone makes a valid claim; returns the winner's name and score
+fn serve(
+    listener: &TcpListener,
+    players: usize,
+    generator: &Generator,
+    patterns: &[WinPattern],
+    speed: Duration,
+    rng: &mut Rng,
+) -> Result<Option<(String, usize)>, Error> {
+    let joined = accept_players(listener, players, JOIN_TIMEOUT)?;
+    let boards = generator.generate(joined.len(), rng)?;
+    check_patterns(patterns, &boards)?;
+    let mut draw_numbers = (generator.min..=generator.max).collect::<Vec<_>>();
+    rng.shuffle(&mut draw_numbers);
+
+    let (events, received) = mpsc::channel();
+    let mut players = vec![];
+    for (idx, ((name, stream, reader), board)) in joined.into_iter().zip(boards).enumerate() {
+        let events = events.clone();
+        thread::spawn(move || read_client(idx, reader, events));
+        let mut player = Player {
+            name,
+            board,
+            stream,
+            connected: true,
+        };
+        player.send(&render_board_message(&player.board));
+        players.push(player);
+    }
+    drop(events);
+
+    let broadcast = |players: &mut [Player], message: &str| {
+        for player in players.iter_mut() {
+            player.send(message);
+        }
+    };
+    let mut winner = None;
+    'game: for draw_number in draw_numbers {
+        broadcast(&mut players, &format!("DRAW {}", draw_number));
+        for player in players.iter_mut() {
+            player.board.draw(draw_number);
+        }
+        let deadline = Instant::now() + speed;
+        loop {
+            let event =
+                match received.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(event) => event,
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break 'game,
+                };
+            match event {
+                ClientEvent::Claim(idx) if players[idx].board.finished(patterns) => {
+                    let score = players[idx].board.score() * draw_number;
+                    let name = players[idx].name.clone();
+                    broadcast(&mut players, &format!("WINNER {} {}", name, score));
+                    winner = Some((name, score));
+                    break 'game;
+                }
+                ClientEvent::Claim(idx) => players[idx].send("REJECTED not a bingo"),
+                ClientEvent::Invalid(idx, reason) => {
+                    players[idx].send(&format!("ERROR {}", reason))
+                }
+                ClientEvent::Left(idx) => players[idx].connected = false,
+            }
+        }
+        if players.iter().all(|p| !p.connected) {
+            break;
         }
-        boards = boards.into_iter().filter(|b| !b.finished()).collect();
     }
-    0
+    broadcast(&mut players, "END");
+    for player in &players {
+        let _ = player.stream.shutdown(Shutdown::Both);
+    }
+    Ok(winner)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
-    const TEST_BOARDS: [Board; 3] = [
-        Board {
-            squares: [
-                [
-                    Square::with_number("22"),
-                    Square::with_number("13"),
-                    Square::with_number("17"),
-                    Square::with_number("11"),
-                    Square::with_number("0"),
-                ],
-                [
-                    Square::with_number("8"),
-                    Square::with_number("2"),
-                    Square::with_number("23"),
-                    Square::with_number("4"),
-                    Square::with_number("24"),
-                ],
-                [
-                    Square::with_number("21"),
-                    Square::with_number("9"),
-                    Square::with_number("14"),
-                    Square::with_number("16"),
-                    Square::with_number("7"),
-                ],
-                [
-                    Square::with_number("6"),
-                    Square::with_number("10"),
-                    Square::with_number("3"),
-                    Square::with_number("18"),
-                    Square::with_number("5"),
-                ],
-                [
-                    Square::with_number("1"),
-                    Square::with_number("12"),
-                    Square::with_number("20"),
-                    Square::with_number("15"),
-                    Square::with_number("19"),
-                ],
-            ],
-        },
-        Board {
-            squares: [
-                [
-                    Square::with_number("3"),
-                    Square::with_number("15"),
-                    Square::with_number("0"),
-                    Square::with_number("2"),
-                    Square::with_number("22"),
-                ],
-                [
-                    Square::with_number("9"),
-                    Square::with_number("18"),
-                    Square::with_number("13"),
-                    Square::with_number("17"),
-                    Square::with_number("5"),
-                ],
-                [
-                    Square::with_number("19"),
-                    Square::with_number("8"),
-                    Square::with_number("7"),
-                    Square::with_number("25"),
-                    Square::with_number("23"),
-                ],
-                [
-                    Square::with_number("20"),
-                    Square::with_number("11"),
-                    Square::with_number("10"),
-                    Square::with_number("24"),
-                    Square::with_number("4"),
-                ],
-                [
-                    Square::with_number("14"),
-                    Square::with_number("21"),
-                    Square::with_number("16"),
-                    Square::with_number("12"),
-                    Square::with_number("6"),
-                ],
-            ],
-        },
-        Board {
-            squares: [
-                [
-                    Square::with_number("14"),
-                    Square::with_number("21"),
-                    Square::with_number("17"),
-                    Square::with_number("24"),
-                    Square::with_number("4"),
-                ],
-                [
-                    Square::with_number("10"),
-                    Square::with_number("16"),
-                    Square::with_number("15"),
-                    Square::with_number("9"),
-                    Square::with_number("19"),
-                ],
-                [
-                    Square::with_number("18"),
-                    Square::with_number("8"),
-                    Square::with_number("23"),
-                    Square::with_number("26"),
-                    Square::with_number("20"),
+    const DRAW_NUMBERS: &str =
+        "7,4,9,5,11,17,23,2,0,14,21,24,10,16,13,6,15,25,12,22,18,20,8,19,3,26,1";
+
+    impl Board {
+        fn parse(input: &[&str]) -> Result<Self, Error> {
+            let lines = input
+                .iter()
+                .enumerate()
+                .map(|(idx, line)| (idx + 1, *line))
+                .collect::<Vec<_>>();
+            Self::parse_numbered(&lines, false)
+        }
+    }
+
+    fn test_draw_numbers() -> Vec<usize> {
+        DRAW_NUMBERS
+            .split(',')
+            .map(|n| n.parse().unwrap())
+            .collect()
+    }
+
+    fn test_boards() -> Vec<Board> {
+        vec![
+            Board {
+                rows: 5,
+                cols: 5,
+                squares: vec![
+                    Square::with_number(22),
+                    Square::with_number(13),
+                    Square::with_number(17),
+                    Square::with_number(11),
+                    Square::with_number(0),
+                    Square::with_number(8),
+                    Square::with_number(2),
+                    Square::with_number(23),
+                    Square::with_number(4),
+                    Square::with_number(24),
+                    Square::with_number(21),
+                    Square::with_number(9),
+                    Square::with_number(14),
+                    Square::with_number(16),
+                    Square::with_number(7),
+                    Square::with_number(6),
+                    Square::with_number(10),
+                    Square::with_number(3),
+                    Square::with_number(18),
+                    Square::with_number(5),
+                    Square::with_number(1),
+                    Square::with_number(12),
+                    Square::with_number(20),
+                    Square::with_number(15),
+                    Square::with_number(19),
                 ],
-                [
-                    Square::with_number("22"),
-                    Square::with_number("11"),
-                    Square::with_number("13"),
-                    Square::with_number("6"),
-                    Square::with_number("5"),
+            },
+            Board {
+                rows: 5,
+                cols: 5,
+                squares: vec![
+                    Square::with_number(3),
+                    Square::with_number(15),
+                    Square::with_number(0),
+                    Square::with_number(2),
+                    Square::with_number(22),
+                    Square::with_number(9),
+                    Square::with_number(18),
+                    Square::with_number(13),
+                    Square::with_number(17),
+                    Square::with_number(5),
+                    Square::with_number(19),
+                    Square::with_number(8),
+                    Square::with_number(7),
+                    Square::with_number(25),
+                    Square::with_number(23),
+                    Square::with_number(20),
+                    Square::with_number(11),
+                    Square::with_number(10),
+                    Square::with_number(24),
+                    Square::with_number(4),
+                    Square::with_number(14),
+                    Square::with_number(21),
+                    Square::with_number(16),
+                    Square::with_number(12),
+                    Square::with_number(6),
                 ],
-                [
-                    Square::with_number("2"),
-                    Square::with_number("0"),
-                    Square::with_number("12"),
-                    Square::with_number("3"),
-                    Square::with_number("7"),
+            },
+            Board {
+                rows: 5,
+                cols: 5,
+                squares: vec![
+                    Square::with_number(14),
+                    Square::with_number(21),
+                    Square::with_number(17),
+                    Square::with_number(24),
+                    Square::with_number(4),
+                    Square::with_number(10),
+                    Square::with_number(16),
+                    Square::with_number(15),
+                    Square::with_number(9),
+                    Square::with_number(19),
+                    Square::with_number(18),
+                    Square::with_number(8),
+                    Square::with_number(23),
+                    Square::with_number(26),
+                    Square::with_number(20),
+                    Square::with_number(22),
+                    Square::with_number(11),
+                    Square::with_number(13),
+                    Square::with_number(6),
+                    Square::with_number(5),
+                    Square::with_number(2),
+                    Square::with_number(0),
+                    Square::with_number(12),
+                    Square::with_number(3),
+                    Square::with_number(7),
                 ],
-            ],
-        },
-    ];
+            },
+        ]
+    }
 
     #[test]
     fn test_parse_boards() {
@@ -273,46 +2163,777 @@ mod tests {
  2  0 12  3  7
         "#;
 
-        let expected = TEST_BOARDS;
-        let input = input
-            .split('\n')
-            .map(str::trim)
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<_>>();
-        let actual = {
-            let mut boards = Vec::<Board>::new();
-            for offset in (0..input.len()).step_by(BOARD_SIZE) {
-                match input.get(offset..offset + BOARD_SIZE) {
-                    Some(input) => boards.push(Board::parse(input)),
-                    None => break,
-                }
-            }
-            boards
-        };
-        assert_eq!(&expected[..], &actual);
+        let expected = test_boards();
+        let input = format!("{}\n\n{}", DRAW_NUMBERS, input);
+        let (draw_numbers, actual) = parse_game(&input, BoardSize::FromFirst, false).unwrap();
+        assert_eq!(27, draw_numbers.len());
+        assert_eq!(expected, actual);
+        let (_, actual) = parse_game(&input, BoardSize::Fixed(5, 5), false).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test_case("1,2\n\n1 2\n3 x", "line 4: invalid number \"x\""; "non-numeric square")]
+    #[test_case("1,2\n\n1 2\n3 4 5", "line 4: expected 2 numbers but found 3"; "ragged row")]
+    #[test_case("1,2\n\n1 2\n3 4\n\n5 6\n7 5", "line 7: duplicate number 5 (already on line 6)"; "duplicate")]
+    #[test_case("\n1,a,2\n\n1 2\n3 4", "line 2: invalid draw number \"a\""; "non-numeric draw")]
+    #[test_case("1,,2\n\n1 2\n3 4", "line 1: invalid draw number \"\""; "empty draw")]
+    #[test_case("1 2 3\n\n1 2\n3 4", "line 1: invalid draw number \"1 2 3\""; "draws without commas")]
+    #[test_case("1,2\n\n", "no boards after the draw numbers on line 1"; "no boards")]
+    #[test_case("\n\n", "missing draw numbers"; "empty")]
+    #[test_case("1,2\n\n1 2\n3 4\n\n5 6\n", "line 6: expected a 2x2 board but found 1x2"; "truncated board")]
+    #[test_case("1,2\n\n1 2\n3 4\n\n5 6 8\n7 9 10\n", "line 6: expected a 2x2 board but found 2x3"; "wider board")]
+    fn test_parse_game_errors(input: &str, expected: &str) {
+        let error = parse_game(input, BoardSize::FromFirst, false).unwrap_err();
+        assert_eq!(expected, error.to_string());
+    }
+
+    #[test]
+    fn test_parse_game_sized_errors() {
+        let input = "1,2\n1 2\n3 4\n5 6\n";
+        let error = parse_game(input, BoardSize::Fixed(2, 2), false).unwrap_err();
+        assert_eq!(
+            "line 4: expected a 2x2 board but found 1x2",
+            error.to_string()
+        );
+        let input = "1,2\n1 2\n1 4\n";
+        assert!(parse_game(input, BoardSize::Fixed(2, 2), false).is_err());
+        let (_, boards) = parse_game(input, BoardSize::Fixed(2, 2), true).unwrap();
+        assert_eq!(8, boards[0].score());
     }
 
     #[test]
     fn test_play_bingo() {
-        let draw_numbers = "7,4,9,5,11,17,23,2,0,14,21,24,10,16,13,6,15,25,12,22,18,20,8,19,3,26,1"
-            .split(',')
-            .map(str::trim)
-            .collect::<Vec<_>>();
+        let draw_numbers = test_draw_numbers();
 
         let expected = 4512;
-        let actual = play_bingo(&TEST_BOARDS, &draw_numbers);
+        let actual = play_bingo(&test_boards(), &draw_numbers, LINES);
         assert_eq!(expected, actual);
     }
 
     #[test]
     fn test_play_bingo_to_lose() {
-        let draw_numbers = "7,4,9,5,11,17,23,2,0,14,21,24,10,16,13,6,15,25,12,22,18,20,8,19,3,26,1"
-            .split(',')
-            .map(str::trim)
-            .collect::<Vec<_>>();
+        let draw_numbers = test_draw_numbers();
 
         let expected = 1924;
-        let actual = play_bingo_to_lose(&TEST_BOARDS, &draw_numbers);
+        let actual = play_bingo_to_lose(&test_boards(), &draw_numbers, LINES);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_rectangular_boards() {
+        let input = "3,1,4,1,5,9\n\n1 2 3 4\n5 6 7 8\n\n9 8 7 6\n5 4 3 2\n";
+        let (draw_numbers, boards) = parse_game(input, BoardSize::FromFirst, false).unwrap();
+        assert_eq!(2, boards.len());
+        assert_eq!((2, 4), (boards[0].rows, boards[0].cols));
+        assert!(parse_game(input, BoardSize::Fixed(4, 2), false).is_err());
+
+        let mut board = boards[0].clone();
+        [5, 6, 7].iter().for_each(|n| board.draw(*n));
+        assert!(!board.finished(LINES));
+        board.draw(8);
+        assert!(board.finished(LINES));
+        assert_eq!(10, board.score());
+
+        // board 0 completes the column "1 5" when 5 is drawn
+        assert_eq!(
+            (2 + 6 + 7 + 8) * 5,
+            play_bingo(&boards, &draw_numbers, LINES)
+        );
+    }
+
+    #[test]
+    fn test_mixed_sizes() {
+        let input = "11,2,3\n\n1 2 3\n4 5 6\n7 8 9\n\n11 12 13\n3 4 14\n";
+        let error = parse_game(input, BoardSize::FromFirst, true).unwrap_err();
+        assert_eq!(
+            "line 7: expected a 3x3 board but found 2x3",
+            error.to_string()
+        );
+        let (draw_numbers, boards) = parse_game(input, BoardSize::Any, true).unwrap();
+        assert_eq!(
+            vec![(3, 3), (2, 3)],
+            boards.iter().map(|b| (b.rows, b.cols)).collect::<Vec<_>>()
+        );
+        // the 2x3 board completes its column "11 3" before the 3x3 board has a line
+        assert_eq!(
+            (12 + 13 + 4 + 14) * 3,
+            play_bingo(&boards, &draw_numbers, LINES)
+        );
+    }
+
+    #[test]
+    fn test_three_by_three_columns() {
+        let input = "2,5,8\n1 2 3\n4 5 6\n7 8 9\n";
+        let (draw_numbers, boards) = parse_game(input, BoardSize::Fixed(3, 3), false).unwrap();
+        assert_eq!(
+            (1 + 3 + 4 + 6 + 7 + 9) * 8,
+            play_bingo(&boards, &draw_numbers, LINES)
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!((3, 7), parse_size("3x7").unwrap());
+        assert!(parse_size("0x7").is_err());
+        assert!(parse_size("37").is_err());
+    }
+
+    const THREE_BY_THREE: &str = "1 2 3\n4 5 6\n7 8 9";
+
+    fn marked_board(marks: &[usize]) -> Board {
+        let mut board = Board::parse(&THREE_BY_THREE.split('\n').collect::<Vec<_>>()).unwrap();
+        marks.iter().for_each(|n| board.draw(*n));
+        board
+    }
+
+    #[test_case(&[1, 5, 9], "diagonals", true)]
+    #[test_case(&[3, 5, 7], "diagonals", true)]
+    #[test_case(&[3, 5, 7], "rows,columns", false)]
+    #[test_case(&[1, 2, 3], "diagonals", false)]
+    #[test_case(&[1, 3, 7, 9], "corners", true)]
+    #[test_case(&[1, 3, 7, 9], "x", false)]
+    #[test_case(&[1, 3, 5, 7, 9], "x", true)]
+    #[test_case(&[1, 2, 3, 4, 5, 6, 7, 8], "blackout", false)]
+    #[test_case(&[1, 2, 3, 4, 5, 6, 7, 8, 9], "blackout", true)]
+    #[test_case(&[2, 4, 6, 8], "mask:.#./#.#/.#.", true)]
+    #[test_case(&[2, 4, 6], "mask:.#./#.#/.#.", false)]
+    #[test_case(&[1, 2, 3], "columns,mask:.#./#.#/.#.", false)]
+    #[test_case(&[1, 2, 3], "lines", true)]
+    fn test_win_patterns(marks: &[usize], patterns: &str, expected: bool) {
+        let patterns = parse_patterns(patterns).unwrap();
+        let actual = marked_board(marks).finished(&patterns);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_no_diagonals_on_rectangular_boards() {
+        assert!(WinPattern::Diagonals.lines(2, 3).is_empty());
+        assert!(WinPattern::X.lines(2, 3).is_empty());
+        assert_eq!(vec![vec![0, 2, 3, 5]], WinPattern::Corners.lines(2, 3));
+    }
+
+    #[test_case("mask:#./#" ; "ragged mask")]
+    #[test_case("mask:#o/.." ; "unexpected character")]
+    #[test_case("mask:../.." ; "empty mask")]
+    #[test_case("triangle" ; "unknown pattern")]
+    fn test_parse_patterns_invalid(input: &str) {
+        assert!(parse_patterns(input).is_err());
+    }
+
+    #[test]
+    fn test_check_patterns() {
+        let patterns = parse_patterns("lines,mask:##/##").unwrap();
+        assert!(check_patterns(&patterns, &test_boards()).is_err());
+        assert!(check_patterns(LINES, &test_boards()).is_ok());
+    }
+
+    #[test]
+    fn test_play_bingo_patterns() {
+        let draw_numbers = test_draw_numbers();
+        // board 2's anti-diagonal "4 9 23 11 2" is complete once 2 is drawn
+        let diagonals = parse_patterns("diagonals").unwrap();
+        assert_eq!(494, play_bingo(&test_boards(), &draw_numbers, &diagonals));
+        // every number is eventually drawn, so the first blackout leaves nothing unmarked
+        let blackout = parse_patterns("blackout").unwrap();
+        assert_eq!(0, play_bingo(&test_boards(), &draw_numbers, &blackout));
+    }
+
+    #[test]
+    fn test_play_all() {
+        let draw_numbers = test_draw_numbers();
+        let results = play_all(&test_boards(), &draw_numbers, LINES);
+        let actual = results
+            .iter()
+            .map(|result| (result.won_at, result.winning_number, result.score))
+            .collect::<Vec<_>>();
+        let expected = vec![
+            (Some(13), Some(16), Some(137 * 16)),
+            (Some(14), Some(13), Some(148 * 13)),
+            (Some(11), Some(24), Some(4512)),
+        ];
         assert_eq!(expected, actual);
+        // board 2's top row
+        assert_eq!(Some(vec![0, 1, 2, 3, 4]), results[2].winning_line);
+    }
+
+    #[test]
+    fn test_play_all_agrees_with_play_bingo() {
+        let draw_numbers = test_draw_numbers();
+        let results = play_all(&test_boards(), &draw_numbers, LINES);
+        let first = results.iter().min_by_key(|r| r.won_at).unwrap();
+        let last = results.iter().max_by_key(|r| r.won_at).unwrap();
+        assert_eq!(
+            Some(play_bingo(&test_boards(), &draw_numbers, LINES)),
+            first.score
+        );
+        assert_eq!(
+            Some(play_bingo_to_lose(&test_boards(), &draw_numbers, LINES)),
+            last.score
+        );
+    }
+
+    #[test]
+    fn test_render_results() {
+        let boards = vec![marked_board(&[]), marked_board(&[]), marked_board(&[])];
+        let draw_numbers = [1, 2, 3];
+        let patterns = parse_patterns("rows").unwrap();
+        let mut results = play_all(&boards[..1], &draw_numbers, &patterns);
+        results.push(results[0].clone());
+        results[1].board = 1;
+        results.push(BoardResult {
+            board: 2,
+            won_at: None,
+            winning_number: None,
+            score: None,
+            winning_line: None,
+        });
+        let expected = "\
+rank  board   draw  number   score  line
+   1      1      3       3     117  1 2 3
+   1      2      3       3     117  1 2 3
+   -      3      -       -       -  -
+";
+        assert_eq!(expected, render_results(&boards, &results));
+    }
+
+    // the straightforward model: every board scans all of its squares and lines on every draw
+    fn play_all_scanning(
+        boards: &[Board],
+        draw_numbers: &[usize],
+        patterns: &[WinPattern],
+    ) -> Vec<BoardResult> {
+        let mut boards = boards.to_vec();
+        let mut results = (0..boards.len())
+            .map(|board| BoardResult {
+                board,
+                won_at: None,
+                winning_number: None,
+                score: None,
+                winning_line: None,
+            })
+            .collect::<Vec<_>>();
+        for (idx, draw_number) in draw_numbers.iter().enumerate() {
+            for (board, result) in boards.iter_mut().zip(results.iter_mut()) {
+                if result.won_at.is_some() {
+                    continue;
+                }
+                board.draw(*draw_number);
+                if let Some(line) = board.winning_line(patterns) {
+                    result.won_at = Some(idx);
+                    result.winning_number = Some(*draw_number);
+                    result.score = Some(board.score() * draw_number);
+                    result.winning_line = Some(line);
+                }
+            }
+        }
+        results
+    }
+
+    // a deterministic tournament: every board is a different stride through 0..pool
+    fn tournament(boards: usize, draws: usize) -> (Vec<usize>, Vec<Board>) {
+        let pool = 100;
+        let boards = (0..boards)
+            .map(|b| Board {
+                rows: 5,
+                cols: 5,
+                squares: (0..25)
+                    .map(|i| Square::with_number((b * 7 + i * (1 + b % 97)) % pool))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        let draw_numbers = (0..draws).map(|i| (i * 37 + i / pool) % pool).collect();
+        (draw_numbers, boards)
+    }
+
+    #[test_case("lines")]
+    #[test_case("lines,diagonals")]
+    #[test_case("x,corners")]
+    #[test_case("blackout")]
+    fn test_indexed_matches_scanning(patterns: &str) {
+        let patterns = parse_patterns(patterns).unwrap();
+        let (draw_numbers, boards) = tournament(200, 300);
+        assert_eq!(
+            play_all_scanning(&boards, &draw_numbers, &patterns),
+            play_all(&boards, &draw_numbers, &patterns)
+        );
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        assert_eq!(
+            play_all_scanning(&boards, &draw_numbers, &patterns),
+            play_all(&boards, &draw_numbers, &patterns)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_numbers_and_draws() {
+        let board = Board::parse_numbered(&[(1, "1 1"), (2, "2 3")], true).unwrap();
+        let mut game = Game::new(&[board], LINES);
+        assert!(game.draw(4).is_empty());
+        assert_eq!(vec![0], game.draw(1));
+        assert_eq!(Some(&[0, 1][..]), game.winning_line(0));
+        assert_eq!(5, game.score(0));
+        assert!(game.draw(1).is_empty());
+        assert!(game.draw(2).is_empty());
+        assert_eq!(3, game.score(0));
+    }
+
+    #[test]
+    fn test_draw_completing_several_lines() {
+        let board = Board::parse_numbered(&[(1, "1 2"), (2, "3 1")], true).unwrap();
+        let mut game = Game::new(&[board], LINES);
+        assert!(game.draw(1).is_empty());
+        assert_eq!(vec![0], game.draw(2));
+        assert_eq!(Some(&[0, 1][..]), game.winning_line(0));
+        assert_eq!(3, game.score(0));
+    }
+
+    // cargo test --release --bin day4 -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_indexed_marking() {
+        use std::time::Instant;
+
+        for (boards, draws) in [(100, 100), (1_000, 1_000), (5_000, 5_000)] {
+            let (draw_numbers, boards) = tournament(boards, draws);
+            let start = Instant::now();
+            let scanning = play_all_scanning(&boards, &draw_numbers, LINES);
+            let scanning_time = start.elapsed();
+            let start = Instant::now();
+            let indexed = play_all(&boards, &draw_numbers, LINES);
+            let indexed_time = start.elapsed();
+            assert_eq!(scanning, indexed);
+            println!(
+                "{:>5} boards, {:>5} draws: scanning {:>10.2?}, indexed {:>10.2?}",
+                boards.len(),
+                draw_numbers.len(),
+                scanning_time,
+                indexed_time
+            );
+        }
+    }
+
+    #[test]
+    fn test_rng_is_reproducible() {
+        let mut items = (0..50).collect::<Vec<_>>();
+        Rng::new(42).shuffle(&mut items);
+        let mut again = (0..50).collect::<Vec<_>>();
+        Rng::new(42).shuffle(&mut again);
+        assert_eq!(items, again);
+        assert_ne!((0..50).collect::<Vec<_>>(), items);
+        items.sort_unstable();
+        assert_eq!((0..50).collect::<Vec<_>>(), items);
+    }
+
+    #[test]
+    fn test_simulate_identical_boards_share_odds() {
+        let board = Board::parse(&["1 2", "3 4"]).unwrap();
+        let odds = simulate(&[board.clone(), board], &[1, 2, 3, 4], LINES, 100, 7);
+        for odds in &odds {
+            assert_eq!(0.5, odds.wins_first);
+            assert_eq!(0.5, odds.wins_last);
+            // a 2x2 board always wins on its 2nd or 3rd draw
+            assert_eq!(100, odds.draws_needed[2] + odds.draws_needed[3]);
+            assert_eq!(0, odds.never_won);
+        }
+    }
+
+    #[test]
+    fn test_simulate() {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        let odds = simulate(&boards, &draw_numbers, LINES, 500, 1);
+        assert_eq!(odds, simulate(&boards, &draw_numbers, LINES, 500, 1));
+        assert_ne!(odds, simulate(&boards, &draw_numbers, LINES, 500, 2));
+        let first = odds.iter().map(|o| o.wins_first).sum::<f64>();
+        let last = odds.iter().map(|o| o.wins_last).sum::<f64>();
+        assert!((first - 1.0).abs() < 1e-9);
+        assert!((last - 1.0).abs() < 1e-9);
+        for odds in &odds {
+            assert_eq!(500, odds.draws_needed.iter().sum::<usize>());
+            assert!(odds.percentile(0.1) <= odds.percentile(0.9));
+            // a 5x5 board needs at least 5 draws
+            assert_eq!(0, odds.draws_needed[..5].iter().sum::<usize>());
+        }
+    }
+
+    #[test]
+    fn test_simulate_board_that_never_wins() {
+        let boards = vec![
+            Board::parse(&["1 2", "3 4"]).unwrap(),
+            Board::parse(&["5 6", "7 8"]).unwrap(),
+        ];
+        let odds = simulate(&boards, &[1, 2, 3, 4], LINES, 10, 0);
+        assert_eq!(1.0, odds[0].wins_first);
+        assert_eq!(0.0, odds[0].wins_last);
+        assert_eq!(1.0, odds[1].wins_last);
+        assert_eq!(10, odds[1].never_won);
+        assert_eq!(None, odds[1].percentile(0.5));
+    }
+
+    // replays a draw order and returns the boards in the order they first won
+    fn winning_order(boards: &[Board], draws: &[usize]) -> Vec<Vec<usize>> {
+        let mut game = Game::new(boards, LINES);
+        draws
+            .iter()
+            .map(|n| game.draw(*n))
+            .filter(|winners| !winners.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn test_shortest_win_first() {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        for board in 0..boards.len() {
+            let order = shortest_win_first(&boards, &draw_numbers, LINES, board).unwrap();
+            assert_eq!(5, order.wins_at);
+            assert_eq!(5, order.draws.len());
+            assert_eq!(vec![board], winning_order(&boards, &order.draws)[0]);
+        }
+    }
+
+    #[test]
+    fn test_shortest_win_first_blocked() {
+        // every line of board 0 is also a line of board 1
+        let boards = vec![
+            Board::parse(&["1 2", "3 4"]).unwrap(),
+            Board::parse(&["2 1", "4 3"]).unwrap(),
+        ];
+        assert_eq!(None, shortest_win_first(&boards, &[1, 2, 3, 4], LINES, 0));
+        // a line that needs a number that is never drawn
+        let boards = vec![Board::parse(&["1 2", "3 9"]).unwrap()];
+        let order = shortest_win_first(&boards, &[1, 2, 3, 4], LINES, 0).unwrap();
+        assert_eq!(vec![1, 2], order.draws);
+    }
+
+    #[test]
+    fn test_order_to_win_last() {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        for board in 0..boards.len() {
+            let order =
+                order_to_win_last(&boards, &draw_numbers, LINES, board, SEARCH_BUDGET).unwrap();
+            assert!(order.exact);
+            let mut draws = order.draws.clone();
+            draws.sort_unstable();
+            let mut pool = draw_numbers.clone();
+            pool.sort_unstable();
+            assert_eq!(pool, draws);
+            let winners = winning_order(&boards, &order.draws);
+            assert_eq!(&vec![board], winners.last().unwrap());
+            assert_eq!(boards.len(), winners.len());
+        }
+    }
+
+    #[test]
+    fn test_order_to_win_last_impossible() {
+        // on a single row every square is also a column, so holding back enough numbers to keep
+        // board 1 from winning leaves board 0 nothing to complete
+        let boards = vec![
+            Board::parse(&["1 2"]).unwrap(),
+            Board::parse(&["1 2 3"]).unwrap(),
+        ];
+        let order = order_to_win_last(&boards, &[1, 2, 3], LINES, 1, SEARCH_BUDGET);
+        assert_eq!(Err(Exhausted { exact: true }), order);
+        // without the budget to check any held back set, the greedy search can't prove it
+        let order = order_to_win_last(&boards, &[1, 2, 3], LINES, 1, 0);
+        assert_eq!(Err(Exhausted { exact: false }), order);
+        let order = order_to_win_last(&boards, &[1, 2, 3], LINES, 0, SEARCH_BUDGET).unwrap();
+        assert_eq!(vec![vec![1], vec![0]], winning_order(&boards, &order.draws));
+        let order = order_to_win_last(&boards, &[1, 2, 3], LINES, 0, 0).unwrap();
+        assert!(!order.exact);
+        assert_eq!(vec![vec![1], vec![0]], winning_order(&boards, &order.draws));
+    }
+
+    #[test]
+    fn test_greedy_hitting_set() {
+        let board = &test_boards()[0];
+        let pool = test_draw_numbers().into_iter().collect::<HashSet<_>>();
+        let lines = line_numbers(board, &pool, LINES);
+        let held = greedy_hitting_set(&lines, &[]);
+        assert_eq!(5, held.len());
+        assert!(lines
+            .iter()
+            .all(|line| line.iter().any(|n| held.contains(n))));
+    }
+
+    #[test]
+    fn test_render_board_round_trips() {
+        let boards = test_boards();
+        let expected =
+            "22 13 17 11  0\n 8  2 23  4 24\n21  9 14 16  7\n 6 10  3 18  5\n 1 12 20 15 19\n";
+        assert_eq!(expected, render_board(&boards[0]));
+        let lines = expected.lines().collect::<Vec<_>>();
+        assert_eq!(boards[0], Board::parse(&lines).unwrap());
+    }
+
+    #[test]
+    fn test_generate_game_round_trips() {
+        let generator = Generator::default();
+        let mut rng = Rng::new(5);
+        let boards = generator.generate(20, &mut rng).unwrap();
+        let game = generator.render_game(&boards, &mut rng);
+        let (draw_numbers, parsed) = parse_game(&game, BoardSize::FromFirst, false).unwrap();
+        assert_eq!(boards, parsed);
+        assert_eq!(
+            (0..100).collect::<HashSet<_>>(),
+            draw_numbers.into_iter().collect()
+        );
+        let mut again = Rng::new(5);
+        assert_eq!(boards, generator.generate(20, &mut again).unwrap());
+    }
+
+    #[test]
+    fn test_generate_constraints() {
+        let generator = Generator {
+            rows: 3,
+            cols: 4,
+            min: 1,
+            max: 12,
+            ..Generator::default()
+        };
+        let boards = generator.generate(30, &mut Rng::new(0)).unwrap();
+        let distinct = boards
+            .iter()
+            .map(|b| b.squares.iter().map(|s| s.number).collect::<Vec<_>>())
+            .collect::<HashSet<_>>();
+        assert_eq!(30, distinct.len());
+        for board in &boards {
+            assert_eq!((3, 4), (board.rows, board.cols));
+            let numbers = board
+                .squares
+                .iter()
+                .map(|s| s.number)
+                .collect::<HashSet<_>>();
+            assert_eq!((1..=12).collect::<HashSet<_>>(), numbers);
+        }
+    }
+
+    #[test]
+    fn test_generate_column_ranges() {
+        let generator = Generator {
+            min: 1,
+            max: 75,
+            column_ranges: true,
+            ..Generator::default()
+        };
+        assert_eq!((1, 15), generator.column_range(0));
+        assert_eq!((61, 75), generator.column_range(4));
+        for board in generator.generate(50, &mut Rng::new(9)).unwrap() {
+            for (idx, square) in board.squares.iter().enumerate() {
+                let (min, max) = generator.column_range(idx % 5);
+                assert!((min..=max).contains(&square.number));
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_impossible() {
+        let too_small = Generator {
+            max: 23,
+            ..Generator::default()
+        };
+        assert!(too_small.generate(1, &mut Rng::new(0)).is_err());
+        let duplicates = Generator {
+            max: 23,
+            unique_squares: false,
+            ..Generator::default()
+        };
+        assert!(duplicates.generate(1, &mut Rng::new(0)).is_ok());
+        // a 1x1 board from 0-1 can only be generated twice before repeating itself
+        let tiny = Generator {
+            rows: 1,
+            cols: 1,
+            max: 1,
+            ..Generator::default()
+        };
+        assert!(tiny.generate(2, &mut Rng::new(0)).is_ok());
+        assert!(tiny.generate(3, &mut Rng::new(0)).is_err());
+    }
+
+    #[test_case("", ReplayCommand::Step)]
+    #[test_case("n", ReplayCommand::Step)]
+    #[test_case("play", ReplayCommand::Play(None))]
+    #[test_case("p 20", ReplayCommand::Play(Some(Duration::from_millis(20))))]
+    #[test_case("j 12", ReplayCommand::Jump(12))]
+    #[test_case("q", ReplayCommand::Quit)]
+    fn test_parse_replay_command(input: &str, expected: ReplayCommand) {
+        assert_eq!(expected, input.parse().unwrap());
+    }
+
+    #[test_case("j")]
+    #[test_case("jump x")]
+    #[test_case("q now")]
+    #[test_case("skip")]
+    fn test_parse_replay_command_invalid(input: &str) {
+        assert!(input.parse::<ReplayCommand>().is_err());
+    }
+
+    #[test]
+    fn test_replay_jump() {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        let mut replay = Replay::new(&boards, &draw_numbers, LINES);
+        replay.jump(12);
+        assert_eq!(vec![2], replay.just_won);
+        replay.jump(5);
+        assert!(replay.just_won.is_empty());
+        assert_eq!(5, replay.drawn);
+        let marked = replay.game.boards[2].squares.iter().filter(|s| s.marked);
+        assert_eq!(5, marked.count());
+        replay.jump(1000);
+        assert!(replay.finished());
+    }
+
+    #[test]
+    fn test_replay_render() {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        let mut replay = Replay::new(&boards, &draw_numbers, LINES);
+        replay.jump(12);
+        let expected = "\
+Draw 12 of 27: 24
+1 of 3 boards have won, just won: 3
+
+Board 1               Board 2               Board 3 BINGO!
+22  13  17* 11*  0*    3  15   0*  2* 22    14# 21# 17# 24#  4#
+ 8   2* 23*  4* 24*    9* 18  13  17*  5*   10  16  15   9* 19
+21*  9* 14* 16   7*   19   8   7* 25  23*   18   8  23* 26  20
+ 6  10   3  18   5*   20  11* 10  24*  4*   22  11* 13   6   5*
+ 1  12  20  15  19    14* 21* 16  12   6     2*  0* 12   3   7*
+";
+        assert_eq!(expected, replay.render(Style::Plain));
+    }
+
+    #[test]
+    fn test_replay_run() {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        let mut replay = Replay::new(&boards, &draw_numbers, LINES);
+        let commands = "\n\nj 11\nbogus\np 0\nq\nn\n";
+        let mut out = vec![];
+        replay
+            .run(commands.as_bytes(), &mut out, Duration::ZERO, Style::Plain)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Draw 2 of 27: 4\n"));
+        assert!(out.contains("Unknown command: \"bogus\""));
+        assert!(out.contains("Draw 27 of 27: 1\n"));
+        // quitting stops before the final step
+        assert!(replay.finished());
+        assert_eq!(27, replay.drawn);
+    }
+
+    #[test_case("JOIN alice", ClientMessage::Join("alice".to_string()))]
+    #[test_case("BINGO\r\n", ClientMessage::Bingo)]
+    #[test_case("QUIT", ClientMessage::Quit)]
+    fn test_parse_client_message(input: &str, expected: ClientMessage) {
+        assert_eq!(expected, input.parse().unwrap());
+    }
+
+    #[test_case("JOIN")]
+    #[test_case("JOIN two names")]
+    #[test_case("bingo")]
+    #[test_case(""; "empty")]
+    fn test_parse_client_message_invalid(input: &str) {
+        assert!(input.parse::<ClientMessage>().is_err());
+    }
+
+    #[test]
+    fn test_accept_players_drops_silent_connections() {
+        let listener = TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let clients = thread::spawn(move || {
+            // connects first and never says anything
+            let silent = TcpStream::connect(addr).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            let mut player = TcpStream::connect(addr).unwrap();
+            writeln!(player, "JOIN alice").unwrap();
+            let mut reply = String::new();
+            BufReader::new(&silent).read_line(&mut reply).unwrap();
+            (reply, player)
+        });
+        let joined = accept_players(&listener, 1, Duration::from_millis(200)).unwrap();
+        let (reply, _player) = clients.join().unwrap();
+        assert_eq!("ERROR timed out waiting for JOIN <name>\n", reply);
+        assert_eq!(
+            vec!["alice"],
+            joined
+                .iter()
+                .map(|(name, ..)| name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(None, joined[0].1.read_timeout().unwrap());
+    }
+
+    #[test]
+    fn test_render_board_message() {
+        let board = Board::parse(&["1 2 3", "4 5 6"]).unwrap();
+        assert_eq!("BOARD 2x3 1,2,3,4,5,6", render_board_message(&board));
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        let patterns = parse_patterns("lines,mask:#...#/...../...../...../#...#").unwrap();
+        let mut replay = Replay::new(&boards, &draw_numbers, &patterns);
+        replay.jump(14);
+        let snapshot = replay.snapshot();
+        let text = snapshot.to_string();
+        assert!(text.starts_with(&format!(
+            "draws {}\ndrawn 14\nwin rows,columns,mask:#...#/...../...../...../#...#\nwon 1@14,3@10\n\n22  13  17* 11*  0*\n",
+            DRAW_NUMBERS
+        )));
+
+        let restored = text.parse::<Snapshot>().unwrap();
+        assert_eq!(snapshot, restored);
+        let initial = restored.initial_boards();
+        assert_eq!(boards, initial);
+        let mut resumed = restored.restore(&initial).unwrap();
+        resumed.jump(draw_numbers.len());
+        replay.jump(draw_numbers.len());
+        assert_eq!(replay.won_at, resumed.won_at);
+    }
+
+    #[test]
+    fn test_snapshot_undo() {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        let mut replay = Replay::new(&boards, &draw_numbers, LINES);
+        replay.jump(14);
+        replay.undo(3);
+        assert_eq!(11, replay.drawn);
+        assert_eq!(vec![None; 3], replay.won_at);
+        replay.step();
+        assert_eq!(Some(12), replay.won_at[2]);
+        replay.undo(100);
+        assert_eq!(0, replay.drawn);
+        assert!(replay
+            .game
+            .boards
+            .iter()
+            .flat_map(|b| &b.squares)
+            .all(|s| !s.marked));
+    }
+
+    #[test_case("drawn 14", "drawn 13", "board 1 isn't marked the way the first 13 draws mark it"; "fewer draws")]
+    #[test_case("won 1@14,3@12", "won 3@12", "board 1 is saved as winning at draw never but the draws have it win at 14"; "missing win")]
+    #[test_case("22  13  17* 11*  0*", "22* 13  17* 11*  0*", "board 1 isn't marked the way the first 14 draws mark it"; "extra mark")]
+    #[test_case("drawn 14", "drawn 28", "line 2: 28 draws made but only 27 to make"; "too many draws")]
+    #[test_case("won 1@14,3@12", "won 1@14,4@12", "line 4: no board 4"; "unknown board")]
+    #[test_case("win rows,columns", "win rows,cols", "line 3: Invalid win pattern: \"cols\""; "bad pattern")]
+    #[test_case("drawn 14\n", "", "missing \"drawn\" line"; "missing line")]
+    fn test_snapshot_tampered(from: &str, to: &str, expected: &str) {
+        let boards = test_boards();
+        let draw_numbers = test_draw_numbers();
+        let mut replay = Replay::new(&boards, &draw_numbers, LINES);
+        replay.jump(14);
+        let text = replay.snapshot().to_string().replacen(from, to, 1);
+        let error = text
+            .parse::<Snapshot>()
+            .and_then(|snapshot| snapshot.restore(&snapshot.initial_boards()).map(|_| ()))
+            .unwrap_err();
+        assert_eq!(expected, format!("{:#}", error));
     }
 }
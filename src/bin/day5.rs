@@ -1,19 +1,97 @@
-use anyhow::{anyhow, Error};
-use either::Either;
+use anyhow::{anyhow, bail, Context, Error};
 use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::io::{BufRead, Read, Write};
 use std::str::FromStr;
 
-fn main() {
-    let input = include_str!("../../inputs/day5");
-    let input = input
-        .split('\n')
-        .map(str::trim)
-        .filter(|s| !s.is_empty())
-        .filter_map(|input| input.parse::<Line>().ok())
-        .collect::<Vec<_>>();
+fn main() -> Result<(), Error> {
+    let mut coverage = Coverage::Lattice;
+    let mut backend = None;
+    let mut heatmap = None;
+    let mut output = None;
+    let mut scale = 1;
+    let mut highlight = false;
+    let mut threshold = DANGER_THRESHOLD;
+    let mut histogram = false;
+    let mut clusters = None;
+    let mut live = false;
+    let mut query = None;
+    let mut input_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => input_path = Some(args.next().context("--input needs a path")?),
+            "--raster" => coverage = Coverage::Raster,
+            "--map" => backend = Some(args.next().context("--map needs a backend")?.parse()?),
+            "--heatmap" => {
+                heatmap = Some(args.next().context("--heatmap needs a format")?.parse()?);
+            }
+            "--output" => output = Some(args.next().context("--output needs a path")?),
+            "--scale" => scale = args.next().context("--scale needs a factor")?.parse()?,
+            "--highlight" => highlight = true,
+            "--threshold" => {
+                threshold = args.next().context("--threshold needs a count")?.parse()?;
+            }
+            "--histogram" => histogram = true,
+            "--live" => live = true,
+            "--clusters" => {
+                clusters = Some(args.next().context("--clusters needs 4 or 8")?.parse()?);
+            }
+            // takes the rest of the arguments, e.g. `query rect 0,0 9,9`
+            "query" => query = Some(args.by_ref().join(" ").parse::<Query>()?),
+            _ => bail!("Unknown argument: {:?}", arg),
+        }
+    }
+
+    // every point in the plane is covered at least 0 times
+    if threshold == 0 {
+        bail!("--threshold must be at least 1");
+    }
+    // images would be mixed up with the answers on stdout
+    if heatmap.is_some_and(|format| format != HeatmapFormat::Ascii) && output.is_none() {
+        bail!("image heatmaps need --output");
+    }
+    // --live reads its edits from stdin
+    if live && input_path.as_deref() == Some("-") {
+        bail!("--live can't read the lines from stdin as well");
+    }
+
+    let input = match input_path.as_deref() {
+        Some("-") => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            input
+        }
+        Some(path) => std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?,
+        None => include_str!("../../inputs/day5").to_string(),
+    };
+    let input = parse_lines(&input)?;
+    if let Some(query) = query {
+        // lines are numbered from 1, in input order
+        for idx in LineIndex::new(&input).query(&query) {
+            println!("{}: {}", idx + 1, input[idx]);
+        }
+        return Ok(());
+    }
+    if live {
+        let mut map = IncrementalMap::new(coverage, threshold);
+        for line in &input {
+            map.add(*line);
+        }
+        return map.run(std::io::stdin().lock(), &mut std::io::stdout());
+    }
+    // lattice coverage can be counted without visiting every point, unless a map is asked for
+    // or the threshold isn't the one the analytical count answers
+    let count = |lines: &[Line]| match (coverage, backend) {
+        (Coverage::Lattice, None) if threshold == DANGER_THRESHOLD => count_overlaps(lines),
+        (_, None) => count_dangerous_points(lines, coverage, threshold),
+        (_, Some(backend)) => Map::new(lines, coverage, backend).count_points_covered(threshold),
+    };
     println!(
         "Part 1 answer: {}",
-        count_dangerous_points(
+        count(
             &input
                 .iter()
                 .copied()
@@ -21,119 +99,1305 @@ fn main() {
                 .collect::<Vec<_>>()
         )
     );
-    println!("Part 2 answer: {}", count_dangerous_points(&input));
+    println!("Part 2 answer: {}", count(&input));
+
+    if histogram {
+        let map = Map::new(&input, coverage, backend.unwrap_or(Backend::Auto));
+        print!("{}", map.histogram());
+    }
+
+    if let Some(connectivity) = clusters {
+        let map = Map::new(&input, coverage, backend.unwrap_or(Backend::Auto));
+        for cluster in find_clusters(&input, coverage, &map, threshold, connectivity) {
+            println!(
+                "size {} in {} -> {}, peak {}, lines {}",
+                cluster.size,
+                cluster.min,
+                cluster.max,
+                cluster.peak,
+                // numbered from 1 like the query results
+                cluster.lines.iter().map(|idx| idx + 1).join(",")
+            );
+        }
+    }
+
+    if let Some(format) = heatmap {
+        let heatmap = Heatmap {
+            format,
+            scale,
+            highlight: highlight.then_some(threshold),
+        };
+        let map = Map::new(&input, coverage, backend.unwrap_or(Backend::Auto));
+        let image = heatmap.render(&map)?;
+        match output {
+            Some(path) => {
+                std::fs::write(&path, image).with_context(|| format!("writing {}", path))?
+            }
+            None => std::io::stdout().write_all(&image)?,
+        }
+    }
+    Ok(())
+}
+
+// one line per row of the input, with errors pointing at the 1-based row
+fn parse_lines(input: &str) -> Result<Vec<Line>, Error> {
+    input
+        .split('\n')
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, s)| !s.is_empty())
+        .map(|(idx, s)| {
+            s.parse::<Line>()
+                .with_context(|| format!("line {}: invalid vent line {:?}", idx + 1, s))
+        })
+        .collect()
+}
+
+// which points a line covers: the lattice points exactly on it, or every grid cell (centred on a
+// point) that it passes through
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Coverage {
+    Lattice,
+    Raster,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 struct Point {
-    x: usize,
-    y: usize,
+    x: i64,
+    y: i64,
+}
+
+impl Display for Point {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+impl FromStr for Point {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(',') {
+            Some((x, y)) => Ok(Point {
+                x: x.parse()?,
+                y: y.parse()?,
+            }),
+            None => Err(anyhow!("missing ,")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+struct Line {
+    start: Point,
+    end: Point,
+}
+
+impl FromStr for Line {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(" -> ") {
+            Some((start_point, end_point)) => Ok(Line {
+                start: start_point.parse()?,
+                end: end_point.parse()?,
+            }),
+            None => Err(anyhow!("missing ->")),
+        }
+    }
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.start, self.end)
+    }
+}
+
+impl Line {
+    fn horizontal(&self) -> bool {
+        self.start.y == self.end.y
+    }
+
+    fn vertical(&self) -> bool {
+        self.start.x == self.end.x
+    }
+
+    // the signed distance and direction of a unit step along each axis
+    fn deltas(&self) -> ((u64, i64), (u64, i64)) {
+        let delta = |start: i64, end: i64| (start.abs_diff(end), end.cmp(&start) as i64);
+        (
+            delta(self.start.x, self.end.x),
+            delta(self.start.y, self.end.y),
+        )
+    }
+
+    fn offset(&self, dx: i64, dy: i64) -> Point {
+        Point {
+            x: self.start.x + dx,
+            y: self.start.y + dy,
+        }
+    }
+
+    // the integer points on the line, from start to end: the direction reduced by the gcd of its
+    // components steps from one to the next whatever the slope
+    fn covers_points(&self) -> Vec<Point> {
+        let ((nx, sx), (ny, sy)) = self.deltas();
+        let steps = gcd(nx, ny);
+        if steps == 0 {
+            return vec![self.start];
+        }
+        let (step_x, step_y) = ((nx / steps) as i64 * sx, (ny / steps) as i64 * sy);
+        (0..=steps as i64)
+            .map(|i| self.offset(i * step_x, i * step_y))
+            .collect()
+    }
+
+    // every cell the line passes through, from start to end, stepping diagonally where it
+    // crosses a corner exactly so that 45deg lines cover the same points either way
+    fn raster_points(&self) -> Vec<Point> {
+        let ((nx, sx), (ny, sy)) = self.deltas();
+        let (mut ix, mut iy) = (0, 0);
+        let mut points = vec![self.start];
+        while ix < nx || iy < ny {
+            // compares where the line leaves the current cell along x and along y,
+            // (0.5 + ix) / nx against (0.5 + iy) / ny without the division
+            let (x_edge, y_edge) = ((1 + 2 * ix) * ny, (1 + 2 * iy) * nx);
+            if x_edge <= y_edge {
+                ix += 1;
+            }
+            if y_edge <= x_edge {
+                iy += 1;
+            }
+            points.push(self.offset(ix as i64 * sx, iy as i64 * sy));
+        }
+        points
+    }
+
+    fn points(&self, coverage: Coverage) -> Vec<Point> {
+        match coverage {
+            Coverage::Lattice => self.covers_points(),
+            Coverage::Raster => self.raster_points(),
+        }
+    }
+
+    // how many points `points` returns, or at most that many for raster coverage, without
+    // listing them
+    fn count_points(&self, coverage: Coverage) -> u64 {
+        let ((nx, _), (ny, _)) = self.deltas();
+        match coverage {
+            Coverage::Lattice => gcd(nx, ny) + 1,
+            Coverage::Raster => nx + ny + 1,
+        }
+    }
+}
+
+// points covered by at least this many lines are dangerous
+const DANGER_THRESHOLD: usize = 2;
+
+// boxes with more cells than this are counted in a hash map rather than a grid
+const DENSE_CELLS: usize = 1 << 24;
+
+// a grid only pays for clearing and scanning its cells once the lines cover about one in this
+// many of them; fewer and the hash map is faster (see `bench_map_backends`)
+const DENSE_FILL: u64 = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Backend {
+    // dense when the lines' bounding box is small enough and the lines fill enough of it, sparse
+    // otherwise
+    Auto,
+    Dense,
+    Sparse,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Backend::Auto),
+            "dense" => Ok(Backend::Dense),
+            "sparse" => Ok(Backend::Sparse),
+            _ => bail!("Invalid map backend: {:?}", s),
+        }
+    }
+}
+
+// how many times each point is covered
+#[derive(Debug, PartialEq, Clone)]
+enum Map {
+    // a counter for every point of the bounding box, row-major from `min`
+    Dense {
+        min: Point,
+        width: usize,
+        height: usize,
+        counts: Vec<usize>,
+    },
+    // counters for the covered points only
+    Sparse(HashMap<Point, usize>),
+}
+
+// the smallest and largest corners of the box around the lines, which also holds every cell
+// a line passes through
+fn bounds(lines: &[Line]) -> Option<(Point, Point)> {
+    let points = lines.iter().flat_map(|line| [line.start, line.end]);
+    let (xs, ys): (Vec<_>, Vec<_>) = points.map(|p| (p.x, p.y)).unzip();
+    Some((
+        Point {
+            x: *xs.iter().min()?,
+            y: *ys.iter().min()?,
+        },
+        Point {
+            x: *xs.iter().max()?,
+            y: *ys.iter().max()?,
+        },
+    ))
+}
+
+impl Map {
+    fn new(lines: &[Line], coverage: Coverage, backend: Backend) -> Self {
+        let bounds = bounds(lines);
+        let (min, width, height) = match bounds {
+            Some((min, max)) => (
+                min,
+                max.x.abs_diff(min.x) as usize + 1,
+                max.y.abs_diff(min.y) as usize + 1,
+            ),
+            None => (Point { x: 0, y: 0 }, 0, 0),
+        };
+        // counting points where lines overlap more than once
+        let covered = lines
+            .iter()
+            .map(|line| line.count_points(coverage))
+            .sum::<u64>();
+        let dense = match backend {
+            Backend::Auto => width.checked_mul(height).is_some_and(|cells| {
+                cells <= DENSE_CELLS && cells as u64 <= covered.saturating_mul(DENSE_FILL)
+            }),
+            Backend::Dense => true,
+            Backend::Sparse => false,
+        };
+        let mut map = if dense {
+            Map::Dense {
+                min,
+                width,
+                height,
+                counts: vec![0; width * height],
+            }
+        } else {
+            Map::Sparse(HashMap::new())
+        };
+        for line in lines {
+            for point in line.points(coverage) {
+                map.add(point);
+            }
+        }
+        map
+    }
+
+    fn add(&mut self, point: Point) {
+        match self {
+            Map::Dense {
+                min, width, counts, ..
+            } => counts[(point.y - min.y) as usize * *width + (point.x - min.x) as usize] += 1,
+            Map::Sparse(counts) => *counts.entry(point).or_default() += 1,
+        }
+    }
+
+    fn count(&self, point: Point) -> usize {
+        match self {
+            Map::Dense {
+                min,
+                width,
+                height,
+                counts,
+            } => {
+                let offset = |from: i64, to: i64| usize::try_from(to.checked_sub(from)?).ok();
+                match (offset(min.x, point.x), offset(min.y, point.y)) {
+                    (Some(x), Some(y)) if x < *width && y < *height => counts[y * width + x],
+                    _ => 0,
+                }
+            }
+            Map::Sparse(counts) => counts.get(&point).copied().unwrap_or_default(),
+        }
+    }
+
+    // the corners of the box around the covered points
+    fn extent(&self) -> Option<(Point, Point)> {
+        match self {
+            Map::Dense {
+                min,
+                width,
+                height,
+                counts,
+            } => counts.iter().any(|c| *c > 0).then(|| {
+                let max = Point {
+                    x: min.x + *width as i64 - 1,
+                    y: min.y + *height as i64 - 1,
+                };
+                (*min, max)
+            }),
+            Map::Sparse(counts) => {
+                let (xs, ys): (Vec<_>, Vec<_>) = counts.keys().map(|p| (p.x, p.y)).unzip();
+                Some((
+                    Point {
+                        x: *xs.iter().min()?,
+                        y: *ys.iter().min()?,
+                    },
+                    Point {
+                        x: *xs.iter().max()?,
+                        y: *ys.iter().max()?,
+                    },
+                ))
+            }
+        }
+    }
+
+    fn count_points_covered(&self, threshold: usize) -> usize {
+        self.histogram().at_least(threshold)
+    }
+
+    // every covered point with its count
+    fn covered(&self) -> Vec<(Point, usize)> {
+        match self {
+            Map::Dense {
+                min, width, counts, ..
+            } => counts
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c > 0)
+                .map(|(idx, c)| {
+                    let point = Point {
+                        x: min.x + (idx % width) as i64,
+                        y: min.y + (idx / width) as i64,
+                    };
+                    (point, *c)
+                })
+                .collect(),
+            Map::Sparse(counts) => counts.iter().map(|(p, c)| (*p, *c)).collect(),
+        }
+    }
+
+    fn histogram(&self) -> Histogram {
+        match self {
+            Map::Dense { counts, .. } => Histogram::new(counts.iter().copied()),
+            Map::Sparse(counts) => Histogram::new(counts.values().copied()),
+        }
+    }
+}
+
+fn count_dangerous_points(input: &[Line], coverage: Coverage, threshold: usize) -> usize {
+    Map::new(input, coverage, Backend::Auto).count_points_covered(threshold)
+}
+
+// how many points are covered exactly k times, for every k from 1 up to the most covered point
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Histogram {
+    // indexed by k, so the first is always 0: uncovered points aren't counted
+    exactly: Vec<usize>,
+}
+
+impl Histogram {
+    fn new(counts: impl Iterator<Item = usize>) -> Self {
+        let mut exactly = vec![0];
+        for count in counts.filter(|c| *c > 0) {
+            if count >= exactly.len() {
+                exactly.resize(count + 1, 0);
+            }
+            exactly[count] += 1;
+        }
+        Self { exactly }
+    }
+
+    fn exactly(&self, k: usize) -> usize {
+        self.exactly.get(k).copied().unwrap_or_default()
+    }
+
+    // how many points are covered at least k times, for every k, by summing from the top down
+    fn cumulative(&self) -> Vec<usize> {
+        let mut at_least = self
+            .exactly
+            .iter()
+            .rev()
+            .scan(0, |total, n| {
+                *total += n;
+                Some(*total)
+            })
+            .collect::<Vec<_>>();
+        at_least.reverse();
+        at_least
+    }
+
+    fn at_least(&self, k: usize) -> usize {
+        self.exactly.iter().skip(k.max(1)).sum()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Connectivity {
+    // sharing an edge
+    Four,
+    // sharing an edge or a corner
+    Eight,
+}
+
+impl FromStr for Connectivity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4" => Ok(Connectivity::Four),
+            "8" => Ok(Connectivity::Eight),
+            _ => bail!("Invalid connectivity: {:?}", s),
+        }
+    }
+}
+
+impl Connectivity {
+    fn neighbours(&self, point: Point) -> impl Iterator<Item = Point> {
+        let offsets: &[(i64, i64)] = match self {
+            Connectivity::Four => &[(1, 0), (-1, 0), (0, 1), (0, -1)],
+            Connectivity::Eight => &[
+                (1, 0),
+                (-1, 0),
+                (0, 1),
+                (0, -1),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+            ],
+        };
+        offsets.iter().map(move |(dx, dy)| Point {
+            x: point.x + dx,
+            y: point.y + dy,
+        })
+    }
+}
+
+// a connected region of dangerous points
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Cluster {
+    size: usize,
+    // corners of the bounding box
+    min: Point,
+    max: Point,
+    // the highest count in the region
+    peak: usize,
+    // indices of the lines covering any of its points, in input order
+    lines: Vec<usize>,
+}
+
+// the regions of points covered at least `threshold` times, largest first
+fn find_clusters(
+    lines: &[Line],
+    coverage: Coverage,
+    map: &Map,
+    threshold: usize,
+    connectivity: Connectivity,
+) -> Vec<Cluster> {
+    let dangerous = map
+        .covered()
+        .into_iter()
+        .filter(|(_, c)| *c >= threshold)
+        .collect::<HashMap<_, _>>();
+    let mut cluster_of = HashMap::new();
+    let mut clusters = vec![];
+    for start in dangerous.keys() {
+        if cluster_of.contains_key(start) {
+            continue;
+        }
+        let id = clusters.len();
+        let mut cluster = Cluster {
+            size: 0,
+            min: *start,
+            max: *start,
+            peak: 0,
+            lines: vec![],
+        };
+        cluster_of.insert(*start, id);
+        let mut stack = vec![*start];
+        while let Some(point) = stack.pop() {
+            cluster.size += 1;
+            cluster.min = Point {
+                x: cluster.min.x.min(point.x),
+                y: cluster.min.y.min(point.y),
+            };
+            cluster.max = Point {
+                x: cluster.max.x.max(point.x),
+                y: cluster.max.y.max(point.y),
+            };
+            cluster.peak = cluster.peak.max(dangerous[&point]);
+            for next in connectivity.neighbours(point) {
+                if dangerous.contains_key(&next) && !cluster_of.contains_key(&next) {
+                    cluster_of.insert(next, id);
+                    stack.push(next);
+                }
+            }
+        }
+        clusters.push(cluster);
+    }
+
+    for (idx, line) in lines.iter().enumerate() {
+        let points = line.points(coverage);
+        for id in points.iter().filter_map(|p| cluster_of.get(p)).unique() {
+            clusters[*id].lines.push(idx);
+        }
+    }
+    // ties are broken by position so the order doesn't depend on the hash map's
+    clusters.sort_by_key(|c| (Reverse(c.size), c.min.y, c.min.x, c.max.y, c.max.x));
+    clusters
+}
+
+impl Display for Histogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>7} {:>9} {:>9}", "covered", "exactly", "at least")?;
+        for (k, at_least) in self.cumulative().into_iter().enumerate().skip(1) {
+            writeln!(f, "{:>7} {:>9} {:>9}", k, self.exactly(k), at_least)?;
+        }
+        Ok(())
+    }
+}
+
+// a change to the survey, e.g. `add 0,9 -> 5,9` or `remove 0,9 -> 5,9`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Edit {
+    Add(Line),
+    Remove(Line),
+}
+
+impl FromStr for Edit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = |line: &str| {
+            line.trim()
+                .parse::<Line>()
+                .with_context(|| format!("Invalid edit: {:?}", s))
+        };
+        match s.trim().split_once(' ') {
+            Some(("add", rest)) => Ok(Edit::Add(line(rest)?)),
+            Some(("remove", rest)) => Ok(Edit::Remove(line(rest)?)),
+            _ => bail!("Invalid edit: {:?}", s),
+        }
+    }
+}
+
+// counts kept up to date as lines come and go, so the dangerous points are only recounted where
+// the changed line runs
+#[derive(Debug, PartialEq, Clone)]
+struct IncrementalMap {
+    coverage: Coverage,
+    threshold: usize,
+    // how many copies of each line are on the map
+    lines: HashMap<Line, usize>,
+    counts: HashMap<Point, usize>,
+    dangerous: usize,
+}
+
+impl IncrementalMap {
+    fn new(coverage: Coverage, threshold: usize) -> Self {
+        Self {
+            coverage,
+            threshold,
+            lines: HashMap::new(),
+            counts: HashMap::new(),
+            dangerous: 0,
+        }
+    }
+
+    fn add(&mut self, line: Line) {
+        *self.lines.entry(line).or_default() += 1;
+        for point in line.points(self.coverage) {
+            let count = self.counts.entry(point).or_default();
+            *count += 1;
+            if *count == self.threshold {
+                self.dangerous += 1;
+            }
+        }
+    }
+
+    // false, leaving the map as it was, when the line isn't on it
+    fn remove(&mut self, line: &Line) -> bool {
+        match self.lines.get_mut(line) {
+            Some(copies) if *copies > 1 => *copies -= 1,
+            Some(_) => {
+                self.lines.remove(line);
+            }
+            None => return false,
+        }
+        for point in line.points(self.coverage) {
+            let count = self
+                .counts
+                .get_mut(&point)
+                .expect("points of a line on the map");
+            if *count == self.threshold {
+                self.dangerous -= 1;
+            }
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&point);
+            }
+        }
+        true
+    }
+
+    fn dangerous(&self) -> usize {
+        self.dangerous
+    }
+
+    // applies an edit per line of input, writing the dangerous count after each
+    fn run(&mut self, input: impl BufRead, out: &mut impl Write) -> Result<(), Error> {
+        writeln!(out, "{}", self.dangerous())?;
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse::<Edit>() {
+                Ok(Edit::Add(line)) => self.add(line),
+                Ok(Edit::Remove(line)) => {
+                    if !self.remove(&line) {
+                        writeln!(out, "No such line: {}", line)?;
+                        continue;
+                    }
+                }
+                Err(e) => {
+                    writeln!(out, "{:#}", e)?;
+                    continue;
+                }
+            }
+            writeln!(out, "{}", self.dangerous())?;
+        }
+        Ok(())
+    }
+}
+
+// heatmaps with more pixels than this, about 8000x8000, are refused rather than rendered
+const MAX_HEATMAP_PIXELS: u64 = 1 << 26;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum HeatmapFormat {
+    // the puzzle's diagram: `.` for uncovered points, otherwise the count
+    Ascii,
+    // greyscale, brighter for higher counts
+    Pgm,
+    // colour-mapped from dark blue through red to yellow
+    Ppm,
+}
+
+impl FromStr for HeatmapFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(HeatmapFormat::Ascii),
+            "pgm" => Ok(HeatmapFormat::Pgm),
+            "ppm" => Ok(HeatmapFormat::Ppm),
+            _ => bail!("Invalid heatmap format: {:?}", s),
+        }
+    }
+}
+
+// the box around the covered points, which takes in (0, 0) like the puzzle's diagram whenever
+// the lines reach it
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Heatmap {
+    format: HeatmapFormat,
+    // each point becomes a `scale`x`scale` block of pixels, images only
+    scale: usize,
+    // points covered at least this many times stand out: `#` in ascii, white in greyscale and
+    // bright green in colour
+    highlight: Option<usize>,
+}
+
+// where a count falls between dark blue (0.0) and yellow (1.0), through purple and red
+fn heat_colour(t: f64) -> [u8; 3] {
+    const STOPS: [[f64; 3]; 4] = [
+        [0.0, 0.0, 80.0],
+        [140.0, 0.0, 160.0],
+        [230.0, 30.0, 30.0],
+        [255.0, 230.0, 0.0],
+    ];
+    let t = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let idx = (t as usize).min(STOPS.len() - 2);
+    let frac = t - idx as f64;
+    let mut colour = [0; 3];
+    for (channel, value) in colour.iter_mut().enumerate() {
+        let (from, to) = (STOPS[idx][channel], STOPS[idx + 1][channel]);
+        *value = (from + (to - from) * frac).round() as u8;
+    }
+    colour
+}
+
+impl Heatmap {
+    fn render(&self, map: &Map) -> Result<Vec<u8>, Error> {
+        if self.scale == 0 {
+            bail!("the scale must be at least 1");
+        }
+        // cropped to the covered points, so lines far from the origin don't drag in the empty
+        // space between
+        let (origin, max) = map
+            .extent()
+            .unwrap_or((Point { x: 0, y: 0 }, Point { x: -1, y: -1 }));
+        // ascii ignores the scale, with a character per point
+        let scale = match self.format {
+            HeatmapFormat::Ascii => 1,
+            _ => self.scale as u64,
+        };
+        let side = |lo: i64, hi: i64| if hi < lo { 0 } else { hi.abs_diff(lo) + 1 };
+        let (width, height) = (side(origin.x, max.x), side(origin.y, max.y));
+        let pixels = width
+            .checked_mul(height)
+            .and_then(|points| points.checked_mul(scale.checked_mul(scale)?));
+        if pixels.is_none_or(|pixels| pixels > MAX_HEATMAP_PIXELS) {
+            bail!(
+                "a {}x{} heatmap is too large, the most is {} pixels",
+                width as u128 * scale as u128,
+                height as u128 * scale as u128,
+                MAX_HEATMAP_PIXELS
+            );
+        }
+        let counts = (origin.y..=max.y)
+            .map(|y| {
+                (origin.x..=max.x)
+                    .map(|x| map.count(Point { x, y }))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let (width, height) = (counts.first().map_or(0, Vec::len), counts.len());
+        let highlighted = |count: usize| self.highlight.is_some_and(|t| count >= t);
+
+        if self.format == HeatmapFormat::Ascii {
+            let mut out = String::new();
+            for row in &counts {
+                for count in row {
+                    out.push(match count {
+                        0 => '.',
+                        count if highlighted(*count) => '#',
+                        count => char::from_digit((*count).min(35) as u32, 36).unwrap(),
+                    });
+                }
+                out.push('\n');
+            }
+            return Ok(out.into_bytes());
+        }
+
+        let peak = counts
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or_default()
+            .max(1);
+        let pixel = |count: usize| -> Vec<u8> {
+            let t = count as f64 / peak as f64;
+            match (self.format, highlighted(count)) {
+                (HeatmapFormat::Pgm, true) => vec![255],
+                // keeps the top of the range free for highlighted points
+                (HeatmapFormat::Pgm, false) if self.highlight.is_some() => {
+                    vec![(t * 191.0).round() as u8]
+                }
+                (HeatmapFormat::Pgm, false) => vec![(t * 255.0).round() as u8],
+                (_, true) => vec![0, 255, 0],
+                (_, false) if count == 0 => vec![0, 0, 0],
+                (_, false) => heat_colour(t).to_vec(),
+            }
+        };
+        let magic = match self.format {
+            HeatmapFormat::Pgm => "P5",
+            _ => "P6",
+        };
+        // the top left pixel isn't (0, 0) when the map is cropped or has negative coordinates
+        let comment = match origin {
+            Point { x: 0, y: 0 } => String::new(),
+            origin => format!("# origin {}\n", origin),
+        };
+        let mut image = format!(
+            "{}\n{}{} {}\n255\n",
+            magic,
+            comment,
+            width * self.scale,
+            height * self.scale
+        )
+        .into_bytes();
+        for row in &counts {
+            let row = row
+                .iter()
+                .flat_map(|count| pixel(*count).repeat(self.scale))
+                .collect::<Vec<_>>();
+            for _ in 0..self.scale {
+                image.extend_from_slice(&row);
+            }
+        }
+        Ok(image)
+    }
+}
+
+// the infinite line a segment lies on: its direction, reduced and pointing right (or down when
+// vertical), and the cross product of that direction with any point on it
+type Carrier = ((i128, i128), i128);
+
+fn cross(a: (i128, i128), b: (i128, i128)) -> i128 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn dot(a: (i128, i128), b: (i128, i128)) -> i128 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Segment {
+    start: (i128, i128),
+    end: (i128, i128),
+    direction: (i128, i128),
+}
+
+impl From<&Line> for Segment {
+    fn from(line: &Line) -> Self {
+        let start = (line.start.x as i128, line.start.y as i128);
+        let end = (line.end.x as i128, line.end.y as i128);
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let steps = gcd(dx.unsigned_abs() as u64, dy.unsigned_abs() as u64) as i128;
+        // a single point is treated as a horizontal segment of no length
+        let direction = match steps {
+            0 => (1, 0),
+            _ if dx < 0 || (dx == 0 && dy < 0) => (-dx / steps, -dy / steps),
+            _ => (dx / steps, dy / steps),
+        };
+        Self {
+            start,
+            end,
+            direction,
+        }
+    }
+}
+
+impl Segment {
+    fn carrier(&self) -> Carrier {
+        (self.direction, cross(self.direction, self.start))
+    }
+
+    // where the ends lie along the direction; the lattice points between them are spaced by the
+    // squared length of the direction
+    fn span(&self) -> (i128, i128) {
+        let (a, b) = (
+            dot(self.direction, self.start),
+            dot(self.direction, self.end),
+        );
+        (a.min(b), a.max(b))
+    }
+
+    fn x_range(&self) -> (i128, i128) {
+        (self.start.0.min(self.end.0), self.start.0.max(self.end.0))
+    }
+
+    fn y_range(&self) -> (i128, i128) {
+        (self.start.1.min(self.end.1), self.start.1.max(self.end.1))
+    }
+
+    // whether the segments share any point at all, not only a lattice one
+    fn intersects(&self, other: &Segment) -> bool {
+        let side = |a: (i128, i128), b: (i128, i128), p: (i128, i128)| {
+            cross((b.0 - a.0, b.1 - a.1), (p.0 - a.0, p.1 - a.1)).signum()
+        };
+        // with `p` known to be on the line through `a` and `b`
+        let between = |a: (i128, i128), b: (i128, i128), p: (i128, i128)| {
+            a.0.min(b.0) <= p.0 && p.0 <= a.0.max(b.0) && a.1.min(b.1) <= p.1 && p.1 <= a.1.max(b.1)
+        };
+        let (p1, p2, q1, q2) = (self.start, self.end, other.start, other.end);
+        let (d1, d2) = (side(q1, q2, p1), side(q1, q2, p2));
+        let (d3, d4) = (side(p1, p2, q1), side(p1, p2, q2));
+        (d1 * d2 < 0 && d3 * d4 < 0)
+            || (d1 == 0 && between(q1, q2, p1))
+            || (d2 == 0 && between(q1, q2, p2))
+            || (d3 == 0 && between(p1, p2, q1))
+            || (d4 == 0 && between(p1, p2, q2))
+    }
+
+    // whether the segment meets the closed rectangle between two corners
+    fn intersects_rect(&self, min: (i128, i128), max: (i128, i128)) -> bool {
+        let inside = |p: (i128, i128)| min.0 <= p.0 && p.0 <= max.0 && min.1 <= p.1 && p.1 <= max.1;
+        let edge = |start, end| Segment {
+            start,
+            end,
+            direction: (0, 0),
+        };
+        inside(self.start)
+            || inside(self.end)
+            || [
+                edge(min, (max.0, min.1)),
+                edge((max.0, min.1), max),
+                edge(max, (min.0, max.1)),
+                edge((min.0, max.1), min),
+            ]
+            .iter()
+            .any(|edge| self.intersects(edge))
+    }
+
+    fn contains(&self, point: (i128, i128)) -> bool {
+        let (lo, hi) = self.span();
+        let t = dot(self.direction, point);
+        cross(self.direction, point) == cross(self.direction, self.start) && lo <= t && t <= hi
+    }
+
+    // the lattice point where two non-parallel segments cross, if they do
+    fn crossing(&self, other: &Segment) -> Option<(i128, i128)> {
+        if self.start == self.end {
+            return other.contains(self.start).then_some(self.start);
+        }
+        if other.start == other.end {
+            return self.contains(other.start).then_some(other.start);
+        }
+        let r = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+        let s = (other.end.0 - other.start.0, other.end.1 - other.start.1);
+        let qp = (other.start.0 - self.start.0, other.start.1 - self.start.1);
+        let (mut denom, mut t, mut u) = (cross(r, s), cross(qp, s), cross(qp, r));
+        if denom < 0 {
+            (denom, t, u) = (-denom, -t, -u);
+        }
+        if !(0..=denom).contains(&t) || !(0..=denom).contains(&u) {
+            return None;
+        }
+        let (x, y) = (
+            self.start.0 * denom + t * r.0,
+            self.start.1 * denom + t * r.1,
+        );
+        (x % denom == 0 && y % denom == 0).then_some((x / denom, y / denom))
+    }
+}
+
+// the overlapping stretches of each carrier, merged and sorted by span
+struct Overlaps {
+    carriers: HashMap<Carrier, Vec<(i128, i128)>>,
+    directions: Vec<(i128, i128)>,
+}
+
+impl Overlaps {
+    fn new(mut carriers: HashMap<Carrier, Vec<(i128, i128)>>) -> Self {
+        for spans in carriers.values_mut() {
+            spans.sort_unstable();
+            let mut merged: Vec<(i128, i128)> = vec![];
+            for (lo, hi) in spans.drain(..) {
+                match merged.last_mut() {
+                    Some(last) if lo <= last.1 => last.1 = last.1.max(hi),
+                    _ => merged.push((lo, hi)),
+                }
+            }
+            *spans = merged;
+        }
+        let directions = carriers.keys().map(|(d, _)| *d).unique().collect();
+        Self {
+            carriers,
+            directions,
+        }
+    }
+
+    fn points(&self) -> usize {
+        self.carriers
+            .iter()
+            .map(|((direction, _), spans)| {
+                let step = dot(*direction, *direction);
+                spans
+                    .iter()
+                    .map(|(lo, hi)| ((hi - lo) / step + 1) as usize)
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+
+    // how many of the carriers have an overlap at the point
+    fn covering(&self, point: (i128, i128)) -> usize {
+        self.directions
+            .iter()
+            .filter(|&&direction| {
+                let t = dot(direction, point);
+                self.carriers
+                    .get(&(direction, cross(direction, point)))
+                    .and_then(|spans| {
+                        let idx = spans.partition_point(|(lo, _)| *lo <= t);
+                        idx.checked_sub(1).map(|idx| spans[idx])
+                    })
+                    .is_some_and(|(_, hi)| t <= hi)
+            })
+            .count()
+    }
+
+    // the lattice points where overlaps on two different carriers meet, which `points` counts
+    // more than once
+    fn shared_points(&self) -> HashSet<(i128, i128)> {
+        let carriers = self.carriers.keys().collect::<Vec<_>>();
+        let mut shared = HashSet::new();
+        for (i, (d1, o1)) in carriers.iter().enumerate() {
+            for (d2, o2) in &carriers[i + 1..] {
+                // solves cross(d1, p) = o1 and cross(d2, p) = o2
+                let det = cross(*d1, *d2);
+                if det == 0 {
+                    continue;
+                }
+                let (x, y) = (o1 * d2.0 - o2 * d1.0, o1 * d2.1 - o2 * d1.1);
+                if x % det == 0 && y % det == 0 {
+                    let point = (x / det, y / det);
+                    if self.covering(point) > 1 {
+                        shared.insert(point);
+                    }
+                }
+            }
+        }
+        shared
+    }
 }
 
-impl FromStr for Point {
-    type Err = Error;
+// counts the lattice points covered by at least two lines by intersecting the lines pairwise,
+// which stays cheap however long the lines are: lines are swept in order of their leftmost x so
+// only those whose x ranges overlap are compared, collinear overlaps are merged per carrier and
+// counted from their spans, and single crossings are counted unless an overlap already has them
+fn count_overlaps(lines: &[Line]) -> usize {
+    let mut segments = lines.iter().map(Segment::from).collect::<Vec<_>>();
+    segments.sort_unstable_by_key(|segment| segment.x_range().0);
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.split_once(',') {
-            Some((x, y)) => Ok(Point {
-                x: x.parse()?,
-                y: y.parse()?,
-            }),
-            None => Err(anyhow!("missing ,")),
+    let mut carriers = HashMap::<Carrier, Vec<(i128, i128)>>::new();
+    let mut crossings = HashSet::new();
+    let mut active: Vec<Segment> = vec![];
+    for segment in segments {
+        let (min_x, _) = segment.x_range();
+        active.retain(|other| other.x_range().1 >= min_x);
+        for other in &active {
+            let ((y_lo, y_hi), (other_lo, other_hi)) = (segment.y_range(), other.y_range());
+            if y_hi < other_lo || other_hi < y_lo {
+                continue;
+            }
+            if segment.direction != other.direction {
+                crossings.extend(segment.crossing(other));
+            } else if segment.carrier() == other.carrier() {
+                let ((lo, hi), (other_lo, other_hi)) = (segment.span(), other.span());
+                let (lo, hi) = (lo.max(other_lo), hi.min(other_hi));
+                if lo <= hi {
+                    carriers
+                        .entry(segment.carrier())
+                        .or_default()
+                        .push((lo, hi));
+                }
+            }
         }
+        active.push(segment);
     }
+
+    let overlaps = Overlaps::new(carriers);
+    let shared = overlaps.shared_points();
+    let repeated = shared
+        .iter()
+        .map(|point| overlaps.covering(*point) - 1)
+        .sum::<usize>();
+    let crossings = crossings
+        .into_iter()
+        .filter(|point| overlaps.covering(*point) == 0)
+        .count();
+    overlaps.points() - repeated + crossings
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-struct Line {
-    start: Point,
-    end: Point,
+enum Query {
+    // the lines with a lattice point at the point
+    Point(Point),
+    // the lines passing through or touching a rectangle, given by two opposite corners
+    Rect(Point, Point),
+    // the lines crossing or touching a line
+    Line(Line),
 }
 
-impl FromStr for Line {
+impl FromStr for Query {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.split_once(" -> ") {
-            Some((start_point, end_point)) => Ok(Line {
-                start: start_point.parse()?,
-                end: end_point.parse()?,
-            }),
-            None => Err(anyhow!("missing ->")),
+        let (kind, rest) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+        match (kind, rest.split_whitespace().collect::<Vec<_>>().as_slice()) {
+            ("point", [point]) => Ok(Query::Point(point.parse()?)),
+            ("rect", [corner, opposite]) => Ok(Query::Rect(corner.parse()?, opposite.parse()?)),
+            ("line", [start, "->", end]) => Ok(Query::Line(Line {
+                start: start.parse()?,
+                end: end.parse()?,
+            })),
+            _ => bail!(
+                "Invalid query {:?}, expected `point X,Y`, `rect X,Y X,Y` or `line X,Y -> X,Y`",
+                s
+            ),
         }
     }
 }
 
-impl Line {
-    fn horizontal(&self) -> bool {
-        self.start.y == self.end.y
+// buckets the lines by the square cells of a grid that they pass through, sized so that there
+// are about as many cells across as the square root of the number of lines
+struct LineIndex {
+    segments: Vec<Segment>,
+    // the box around the lines, outside of which there's nothing to find
+    bounds: Option<(Point, Point)>,
+    cell: i64,
+    buckets: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl LineIndex {
+    fn new(lines: &[Line]) -> Self {
+        let bounds = bounds(lines);
+        let extent = bounds.map_or(0, |(min, max)| (max.x - min.x).max(max.y - min.y));
+        let across = (lines.len() as f64).sqrt().ceil().max(1.0) as i64;
+        let cell = (extent / across).max(1);
+        let mut index = Self {
+            segments: lines.iter().map(Segment::from).collect(),
+            bounds,
+            cell,
+            buckets: HashMap::new(),
+        };
+        for idx in 0..index.segments.len() {
+            let segment = &index.segments[idx];
+            // cells are closed on every side here so that a line running along the edge
+            // between two of them is found from either
+            let keys = index
+                .segment_cells(segment)
+                .into_iter()
+                .filter(|key| {
+                    let (lo, hi) = index.cell_rect(*key);
+                    segment.intersects_rect(lo, hi)
+                })
+                .collect::<Vec<_>>();
+            for key in keys {
+                index.buckets.entry(key).or_default().push(idx);
+            }
+        }
+        index
     }
 
-    fn vertical(&self) -> bool {
-        self.start.x == self.end.x
+    // the cells overlapping the box, leaving out any beyond the lines
+    fn cells(&self, min: Point, max: Point) -> Vec<(i64, i64)> {
+        let (lo, hi) = match self.bounds {
+            Some(bounds) => bounds,
+            None => return vec![],
+        };
+        let (min, max) = (
+            Point {
+                x: min.x.max(lo.x),
+                y: min.y.max(lo.y),
+            },
+            Point {
+                x: max.x.min(hi.x),
+                y: max.y.min(hi.y),
+            },
+        );
+        let cell = |n: i64| n.div_euclid(self.cell);
+        let (cx, cy) = (cell(min.x)..=cell(max.x), cell(min.y)..=cell(max.y));
+        cx.cartesian_product(cy).collect()
     }
 
-    fn covers_points(&self) -> Vec<Point> {
-        if self.horizontal() || self.vertical() {
-            let mut points = vec![];
-            for x in self.start.x.min(self.end.x)..=self.end.x.max(self.start.x) {
-                for y in self.start.y.min(self.end.y)..=self.end.y.max(self.start.y) {
-                    points.push(Point { x, y })
-                }
-            }
-            points
+    // the cells the segment passes through within the lines' box, a column at a time from the
+    // heights where it enters and leaves the column; this can take in a neighbouring cell, which
+    // the exact checks leave out
+    fn segment_cells(&self, segment: &Segment) -> Vec<(i64, i64)> {
+        let (lo, hi) = match self.bounds {
+            Some((lo, hi)) => ((lo.x as i128, lo.y as i128), (hi.x as i128, hi.y as i128)),
+            None => return vec![],
+        };
+        let cell = self.cell as i128;
+        let (start, end) = if segment.start.0 <= segment.end.0 {
+            (segment.start, segment.end)
         } else {
-            // (unenforced) invariant: diagonal lines are always 45deg
-            let x_iter = if self.start.x <= self.end.x {
-                Either::Left(self.start.x..=self.end.x)
-            } else {
-                Either::Right((self.end.x..=self.start.x).rev())
-            };
-            let y_iter = if self.start.y <= self.end.y {
-                Either::Left(self.start.y..=self.end.y)
-            } else {
-                Either::Right((self.end.y..=self.start.y).rev())
+            (segment.end, segment.start)
+        };
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let columns = (start.0.max(lo.0).div_euclid(cell) - 1)..=end.0.min(hi.0).div_euclid(cell);
+        let (top, bottom) = (lo.1.div_euclid(cell), hi.1.div_euclid(cell));
+        let mut cells = vec![];
+        for cx in columns {
+            let (xa, xb) = (start.0.max(cx * cell), end.0.min((cx + 1) * cell));
+            if xa > xb {
+                continue;
+            }
+            // heights at xa and xb as fractions over dx, or the whole segment when it's vertical
+            let (ya, yb, scale) = match dx {
+                0 => (start.1, end.1, 1),
+                _ => (
+                    start.1 * dx + dy * (xa - start.0),
+                    start.1 * dx + dy * (xb - start.0),
+                    dx,
+                ),
             };
-            x_iter.zip(y_iter).map(|(x, y)| Point { x, y }).collect()
+            let rows = (ya.min(yb).div_euclid(scale * cell) - 1).max(top)
+                ..=yb.max(ya).div_euclid(scale * cell).min(bottom);
+            cells.extend(rows.map(|cy| (cx as i64, cy as i64)));
         }
+        cells
     }
-}
 
-#[derive(Debug, PartialEq, Clone)]
-struct Map {
-    points: Vec<Point>,
-}
+    fn cell_rect(&self, (cx, cy): (i64, i64)) -> ((i128, i128), (i128, i128)) {
+        let cell = self.cell as i128;
+        let (x, y) = (cx as i128 * cell, cy as i128 * cell);
+        ((x, y), (x + cell, y + cell))
+    }
 
-impl FromIterator<Point> for Map {
-    fn from_iter<T: IntoIterator<Item = Point>>(iter: T) -> Self {
-        Self {
-            points: iter.into_iter().collect(),
-        }
+    // the lines in any of the cells that pass the check, by index
+    fn search(&self, cells: Vec<(i64, i64)>, check: impl Fn(&Segment) -> bool) -> Vec<usize> {
+        let mut found = cells
+            .into_iter()
+            .filter_map(|key| self.buckets.get(&key))
+            .flatten()
+            .copied()
+            .filter(|idx| check(&self.segments[*idx]))
+            .collect::<Vec<_>>();
+        found.sort_unstable();
+        found.dedup();
+        found
     }
-}
 
-impl Map {
-    fn count_points_covered(&self, threshold: usize) -> usize {
-        self.points
-            .iter()
-            .into_group_map_by(|point| *point)
-            .iter()
-            .filter(|(_k, v)| v.len() >= threshold)
-            .count()
+    fn covering(&self, point: Point) -> Vec<usize> {
+        let p = (point.x as i128, point.y as i128);
+        self.search(self.cells(point, point), |segment| segment.contains(p))
     }
-}
 
-fn count_dangerous_points(input: &[Line]) -> usize {
-    input
-        .iter()
-        .flat_map(|line| line.covers_points())
-        .collect::<Map>()
-        .count_points_covered(2)
+    fn in_rect(&self, corner: Point, opposite: Point) -> Vec<usize> {
+        let min = Point {
+            x: corner.x.min(opposite.x),
+            y: corner.y.min(opposite.y),
+        };
+        let max = Point {
+            x: corner.x.max(opposite.x),
+            y: corner.y.max(opposite.y),
+        };
+        let (lo, hi) = (
+            (min.x as i128, min.y as i128),
+            (max.x as i128, max.y as i128),
+        );
+        self.search(self.cells(min, max), |segment| {
+            segment.intersects_rect(lo, hi)
+        })
+    }
+
+    fn intersecting(&self, line: &Line) -> Vec<usize> {
+        let query = Segment::from(line);
+        self.search(self.segment_cells(&query), |segment| {
+            segment.intersects(&query)
+        })
+    }
+
+    fn query(&self, query: &Query) -> Vec<usize> {
+        match query {
+            Query::Point(point) => self.covering(*point),
+            Query::Rect(corner, opposite) => self.in_rect(*corner, *opposite),
+            Query::Line(line) => self.intersecting(line),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
     macro_rules! assert_eq_any_order {
         ($left:expr, $right:expr) => {
@@ -157,8 +1421,26 @@ mod tests {
         "5,5 -> 8,2",
     ];
 
+    fn test_lines() -> Vec<Line> {
+        TEST_INPUT
+            .iter()
+            .map(|input| input.parse().unwrap())
+            .collect()
+    }
+
+    // xorshift, so the random cases are the same every run; returns numbers below `below`
+    fn rng(seed: u64) -> impl FnMut(u64) -> i64 {
+        let mut state = seed;
+        move |below| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % below) as i64
+        }
+    }
+
     impl Line {
-        fn from_coords(x1: usize, y1: usize, x2: usize, y2: usize) -> Line {
+        fn from_coords(x1: i64, y1: i64, x2: i64, y2: i64) -> Line {
             Line {
                 start: Point { x: x1, y: y1 },
                 end: Point { x: x2, y: y2 },
@@ -187,6 +1469,28 @@ mod tests {
         assert_eq!(&expected[..], &actual);
     }
 
+    #[test_case("-3,2 -> 4,-5", (-3, 2, 4, -5))]
+    #[test_case("0,-1 -> -10,-1", (0, -1, -10, -1))]
+    fn parsing_signed(input: &str, (x1, y1, x2, y2): (i64, i64, i64, i64)) {
+        let line = input.parse::<Line>().unwrap();
+        assert_eq!(Line::from_coords(x1, y1, x2, y2), line);
+        assert_eq!(input, line.to_string());
+    }
+
+    #[test]
+    fn parse_lines_reports_the_row() {
+        let lines = parse_lines("-1,-2 -> 3,-2\n\n  4,5 -> -6,7  \n").unwrap();
+        assert_eq!(
+            vec![
+                Line::from_coords(-1, -2, 3, -2),
+                Line::from_coords(4, 5, -6, 7)
+            ],
+            lines
+        );
+        let error = parse_lines("0,0 -> 1,1\n\n2,2 -> 3\n").unwrap_err();
+        assert_eq!("line 3: invalid vent line \"2,2 -> 3\"", error.to_string());
+    }
+
     #[test]
     fn covers_points_horizontal() {
         let input = TEST_INPUT[0].parse::<Line>().unwrap();
@@ -239,11 +1543,12 @@ mod tests {
     fn dangerous_points_horizontal_vertical() {
         let expected = 5;
         let actual = count_dangerous_points(
-            &TEST_INPUT
-                .iter()
-                .filter_map(|input| input.parse::<Line>().ok())
+            &test_lines()
+                .into_iter()
                 .filter(|line| line.horizontal() || line.vertical())
                 .collect::<Vec<_>>(),
+            Coverage::Lattice,
+            DANGER_THRESHOLD,
         );
         assert_eq!(expected, actual);
     }
@@ -251,12 +1556,761 @@ mod tests {
     #[test]
     fn dangerous_points() {
         let expected = 12;
-        let actual = count_dangerous_points(
-            &TEST_INPUT
+        let actual = count_dangerous_points(&test_lines(), Coverage::Lattice, DANGER_THRESHOLD);
+        assert_eq!(expected, actual);
+    }
+
+    #[test_case(0, 0, 6, 4, &[(0, 0), (3, 2), (6, 4)])]
+    #[test_case(6, 4, 0, 0, &[(6, 4), (3, 2), (0, 0)])]
+    #[test_case(1, 7, 4, 1, &[(1, 7), (2, 5), (3, 3), (4, 1)])]
+    #[test_case(2, 0, 5, 1, &[(2, 0), (5, 1)])]
+    #[test_case(3, 3, 3, 3, &[(3, 3)])]
+    #[test_case(-2, -1, 4, 2, &[(-2, -1), (0, 0), (2, 1), (4, 2)])]
+    #[test_case(1, -1, -2, -4, &[(1, -1), (0, -2), (-1, -3), (-2, -4)])]
+    fn covers_points_any_slope(x1: i64, y1: i64, x2: i64, y2: i64, expected: &[(i64, i64)]) {
+        let expected = expected
+            .iter()
+            .map(|(x, y)| Point { x: *x, y: *y })
+            .collect::<Vec<_>>();
+        let line = Line::from_coords(x1, y1, x2, y2);
+        assert_eq!(expected, line.covers_points());
+        assert_eq!(expected.len() as u64, line.count_points(Coverage::Lattice));
+    }
+
+    #[test_case(0, 0, 2, 1, &[(0, 0), (1, 0), (1, 1), (2, 1)])]
+    #[test_case(2, 1, 0, 0, &[(2, 1), (1, 1), (1, 0), (0, 0)])]
+    #[test_case(0, 0, 1, 3, &[(0, 0), (0, 1), (1, 2), (1, 3)])]
+    #[test_case(4, 0, 1, 3, &[(4, 0), (3, 1), (2, 2), (1, 3)])]
+    #[test_case(5, 2, 5, 2, &[(5, 2)])]
+    #[test_case(-1, -1, 1, -2, &[(-1, -1), (0, -1), (0, -2), (1, -2)])]
+    fn raster_points(x1: i64, y1: i64, x2: i64, y2: i64, expected: &[(i64, i64)]) {
+        let expected = expected
+            .iter()
+            .map(|(x, y)| Point { x: *x, y: *y })
+            .collect::<Vec<_>>();
+        let line = Line::from_coords(x1, y1, x2, y2);
+        assert_eq!(expected, line.raster_points());
+        assert!(expected.len() as u64 <= line.count_points(Coverage::Raster));
+    }
+
+    #[test]
+    fn deltas_of_far_apart_ends() {
+        let far = 4_000_000_000_000_000_000;
+        let line = Line::from_coords(-far, far, far, -far);
+        assert_eq!(((2 * far as u64, 1), (2 * far as u64, -1)), line.deltas());
+        assert_eq!(2 * far as u64 + 1, line.count_points(Coverage::Lattice));
+        let line = Line::from_coords(i64::MAX, 0, i64::MIN, 0);
+        assert_eq!(((u64::MAX, -1), (0, 0)), line.deltas());
+    }
+
+    #[test]
+    fn dangerous_points_raster() {
+        let lines = test_lines();
+        // every line in the example is axis-aligned or at 45deg, where both agree
+        assert_eq!(
+            12,
+            count_dangerous_points(&lines, Coverage::Raster, DANGER_THRESHOLD)
+        );
+        let lines = [Line::from_coords(0, 0, 2, 1), Line::from_coords(1, 0, 1, 2)];
+        assert_eq!(
+            0,
+            count_dangerous_points(&lines, Coverage::Lattice, DANGER_THRESHOLD)
+        );
+        assert_eq!(
+            2,
+            count_dangerous_points(&lines, Coverage::Raster, DANGER_THRESHOLD)
+        );
+    }
+
+    #[test]
+    fn overlaps_match_covered_points() {
+        let lines = test_lines();
+        assert_eq!(12, count_overlaps(&lines));
+        let straight = lines
+            .iter()
+            .copied()
+            .filter(|line| line.horizontal() || line.vertical())
+            .collect::<Vec<_>>();
+        assert_eq!(5, count_overlaps(&straight));
+    }
+
+    #[test_case(&[(0, 0, 10, 0), (5, 0, 15, 0), (12, 0, 20, 0)], 10; "chained collinear overlaps")]
+    #[test_case(&[(0, 0, 10, 0), (2, 0, 4, 0), (3, 0, 8, 0)], 7; "nested collinear overlaps")]
+    #[test_case(&[(0, 0, 4, 0), (0, 0, 4, 0), (2, 0, 2, 4), (2, 2, 2, 4)], 8; "crossing inside an overlap")]
+    #[test_case(&[(0, 0, 4, 0), (0, 0, 4, 0), (2, 0, 2, 4), (2, 0, 2, 3)], 8; "overlaps sharing a point")]
+    #[test_case(&[(0, 0, 4, 4), (0, 4, 4, 0), (0, 2, 4, 2), (2, 0, 2, 4)], 1; "four lines through a point")]
+    #[test_case(&[(0, 0, 3, 1), (0, 1, 3, 0)], 0; "crossing between lattice points")]
+    #[test_case(&[(0, 0, 6, 4), (3, 0, 3, 9), (0, 0, 12, 8)], 3; "steep and shallow")]
+    #[test_case(&[(3, 3, 3, 3), (3, 3, 3, 3), (0, 3, 9, 3), (1, 1, 1, 1)], 1; "single points")]
+    #[test_case(&[(-5, 0, 5, 0), (0, -5, 0, 5), (-3, -3, 3, 3), (-4, 0, -1, 0)], 5; "around the origin")]
+    fn overlaps(lines: &[(i64, i64, i64, i64)], expected: usize) {
+        let lines = lines
+            .iter()
+            .map(|(x1, y1, x2, y2)| Line::from_coords(*x1, *y1, *x2, *y2))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, count_overlaps(&lines));
+        assert_eq!(
+            count_dangerous_points(&lines, Coverage::Lattice, DANGER_THRESHOLD),
+            count_overlaps(&lines)
+        );
+    }
+
+    #[test]
+    fn overlaps_match_covered_points_randomly() {
+        let mut next = rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let lines = (0..next(12) + 1)
+                .map(|_| {
+                    let (x, y) = (next(16), next(16));
+                    // mostly the puzzle's straight and 45deg lines, which overlap far more often
+                    match next(4) {
+                        0 => Line::from_coords(x, y, next(16), y),
+                        1 => Line::from_coords(x, y, x, next(16)),
+                        2 => {
+                            let len = next(8);
+                            Line::from_coords(x, y, x + len, y + len)
+                        }
+                        _ => Line::from_coords(x, y, next(16), next(16)),
+                    }
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                count_dangerous_points(&lines, Coverage::Lattice, DANGER_THRESHOLD),
+                count_overlaps(&lines),
+                "{:?}",
+                lines
+            );
+        }
+    }
+
+    #[test]
+    fn overlaps_with_huge_coordinates() {
+        let lines = [
+            Line::from_coords(0, 5_000_000, 9_000_000, 5_000_000),
+            Line::from_coords(1_000_000, 5_000_000, 20_000_000, 5_000_000),
+            Line::from_coords(0, 0, 10_000_000, 10_000_000),
+            Line::from_coords(7_000_000, 0, 7_000_000, 30_000_000),
+        ];
+        // the shared stretch of the horizontals, plus (7M, 7M)
+        assert_eq!(8_000_001 + 1, count_overlaps(&lines));
+    }
+
+    #[test]
+    fn map_backends_agree() {
+        let lines = test_lines();
+        let dense = Map::new(&lines, Coverage::Lattice, Backend::Dense);
+        let sparse = Map::new(&lines, Coverage::Lattice, Backend::Sparse);
+        assert!(matches!(
+            dense,
+            Map::Dense {
+                width: 10,
+                height: 10,
+                ..
+            }
+        ));
+        assert!(matches!(sparse, Map::Sparse(_)));
+        for threshold in 1..4 {
+            assert_eq!(
+                dense.count_points_covered(threshold),
+                sparse.count_points_covered(threshold)
+            );
+        }
+        assert_eq!(dense.histogram(), sparse.histogram());
+    }
+
+    #[test]
+    fn histogram_example() {
+        let histogram = test_map().histogram();
+        assert_eq!(vec![0, 27, 10, 2], histogram.exactly);
+        assert_eq!(vec![39, 39, 12, 2], histogram.cumulative());
+        for (k, expected) in [(0, 39), (1, 39), (2, 12), (3, 2), (4, 0)] {
+            assert_eq!(expected, histogram.at_least(k));
+        }
+        assert_eq!(0, histogram.exactly(4));
+        let expected = "\
+covered   exactly  at least
+      1        27        39
+      2        10        12
+      3         2         2
+";
+        assert_eq!(expected, histogram.to_string());
+    }
+
+    #[test]
+    fn histogram_empty() {
+        let histogram = Map::new(&[], Coverage::Lattice, Backend::Sparse).histogram();
+        assert_eq!(0, histogram.at_least(1));
+        assert_eq!("covered   exactly  at least\n", histogram.to_string());
+    }
+
+    #[test]
+    fn map_backend_auto() {
+        let near = [Line::from_coords(5, 5, 9, 5), Line::from_coords(7, 3, 7, 8)];
+        let map = Map::new(&near, Coverage::Lattice, Backend::Auto);
+        assert!(matches!(
+            map,
+            Map::Dense {
+                min: Point { x: 5, y: 3 },
+                width: 5,
+                height: 6,
+                ..
+            }
+        ));
+        assert_eq!(1, map.count_points_covered(2));
+
+        let far = [
+            Line::from_coords(0, 0, 4, 4),
+            Line::from_coords(4, 0, 0, 4),
+            Line::from_coords(1_000_000, 1_000_000, 1_000_000, 1_000_010),
+        ];
+        let map = Map::new(&far, Coverage::Lattice, Backend::Auto);
+        assert!(matches!(map, Map::Sparse(_)));
+        assert_eq!(1, map.count_points_covered(2));
+
+        // a box small enough for a grid, but with the lines covering too little of it
+        let thin = [
+            Line::from_coords(0, 0, 0, 9),
+            Line::from_coords(0, 5, 9, 5),
+            Line::from_coords(999, 999, 999, 999),
+        ];
+        let map = Map::new(&thin, Coverage::Lattice, Backend::Auto);
+        assert!(matches!(map, Map::Sparse(_)));
+        assert_eq!(1, map.count_points_covered(2));
+        assert!(matches!(
+            Map::new(&[], Coverage::Lattice, Backend::Auto),
+            Map::Dense { width: 0, .. }
+        ));
+    }
+
+    // cargo test --release --bin day5 -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_map_backends() {
+        use std::time::Instant;
+
+        let mut next = rng(0x9e37_79b9_7f4a_7c15);
+        for (lines, size) in [(500, 1_000), (5_000, 1_000), (500, 4_000), (5_000, 4_000)] {
+            let lines = (0..lines)
+                .map(|_| {
+                    let (x, y, len) = (next(size as u64), next(size as u64), next(size as u64 / 4));
+                    match next(3) {
+                        0 => Line::from_coords(x, y, (x + len).min(size - 1), y),
+                        1 => Line::from_coords(x, y, x, (y + len).min(size - 1)),
+                        _ => {
+                            let len = len.min(size - 1 - x).min(size - 1 - y);
+                            Line::from_coords(x, y, x + len, y + len)
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+            let start = Instant::now();
+            let dense = Map::new(&lines, Coverage::Lattice, Backend::Dense).count_points_covered(2);
+            let dense_time = start.elapsed();
+            let start = Instant::now();
+            let sparse =
+                Map::new(&lines, Coverage::Lattice, Backend::Sparse).count_points_covered(2);
+            let sparse_time = start.elapsed();
+            let start = Instant::now();
+            let analytical = count_overlaps(&lines);
+            let analytical_time = start.elapsed();
+            assert_eq!(dense, sparse);
+            assert_eq!(dense, analytical);
+            let auto = match Map::new(&lines, Coverage::Lattice, Backend::Auto) {
+                Map::Dense { .. } => "dense",
+                Map::Sparse(_) => "sparse",
+            };
+            println!(
+                "{:>5} lines in {:>5}x{:<5}: dense {:>10.2?}, sparse {:>10.2?}, analytical {:>10.2?}, auto picks {}",
+                lines.len(),
+                size,
+                size,
+                dense_time,
+                sparse_time,
+                analytical_time,
+                auto
+            );
+        }
+    }
+
+    fn test_map() -> Map {
+        let lines = test_lines();
+        Map::new(&lines, Coverage::Lattice, Backend::Auto)
+    }
+
+    #[test]
+    fn heatmap_ascii_matches_the_puzzle() {
+        let expected = "\
+1.1....11.
+.111...2..
+..2.1.111.
+...1.2.2..
+.112313211
+...1.2....
+..1...1...
+.1.....1..
+1.......1.
+222111....
+";
+        let heatmap = Heatmap {
+            format: HeatmapFormat::Ascii,
+            scale: 1,
+            highlight: None,
+        };
+        let actual = heatmap.render(&test_map()).unwrap();
+        assert_eq!(expected, String::from_utf8(actual).unwrap());
+
+        let highlighted = Heatmap {
+            highlight: Some(2),
+            ..heatmap
+        };
+        let actual = String::from_utf8(highlighted.render(&test_map()).unwrap()).unwrap();
+        assert_eq!(Some(".11##1##11"), actual.lines().nth(4));
+    }
+
+    #[test]
+    fn heatmap_images() {
+        let greyscale = Heatmap {
+            format: HeatmapFormat::Pgm,
+            scale: 2,
+            highlight: None,
+        };
+        let image = greyscale.render(&test_map()).unwrap();
+        let header = b"P5\n20 20\n255\n";
+        assert_eq!(&header[..], &image[..header.len()]);
+        let pixels = &image[header.len()..];
+        assert_eq!(400, pixels.len());
+        // (2, 2) is covered twice out of a peak of three, and scaled to a 2x2 block
+        assert_eq!([170, 170], pixels[2 * 2 * 20 + 4..][..2]);
+        assert_eq!([170, 170], pixels[(2 * 2 + 1) * 20 + 4..][..2]);
+        assert_eq!([0, 0], pixels[2..4]);
+
+        let highlighted = Heatmap {
+            highlight: Some(3),
+            ..greyscale
+        };
+        let pixels = highlighted.render(&test_map()).unwrap()[header.len()..].to_vec();
+        assert_eq!(127, pixels[2 * 2 * 20 + 4]);
+        // (4, 4) is the only point covered three times
+        assert_eq!(255, pixels[4 * 2 * 20 + 8]);
+
+        let colour = Heatmap {
+            format: HeatmapFormat::Ppm,
+            scale: 1,
+            highlight: Some(2),
+        };
+        let image = colour.render(&test_map()).unwrap();
+        let header = b"P6\n10 10\n255\n";
+        assert_eq!(&header[..], &image[..header.len()]);
+        let pixels = &image[header.len()..];
+        assert_eq!(300, pixels.len());
+        assert_eq!(heat_colour(1.0 / 3.0), pixels[..3]);
+        assert_eq!([0, 0, 0], pixels[3..6]);
+        assert_eq!([0, 255, 0], pixels[(2 * 10 + 2) * 3..][..3]);
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let shifted = test_lines()
+            .iter()
+            .map(|line| {
+                Line::from_coords(
+                    line.start.x - 5,
+                    line.start.y - 7,
+                    line.end.x - 5,
+                    line.end.y - 7,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(12, count_overlaps(&shifted));
+        for backend in [Backend::Dense, Backend::Sparse] {
+            let map = Map::new(&shifted, Coverage::Lattice, backend);
+            assert_eq!(12, map.count_points_covered(2));
+            assert_eq!(3, map.count(Point { x: -1, y: -3 }));
+            assert_eq!(0, map.count(Point { x: -6, y: -3 }));
+            assert_eq!(
+                Some((Point { x: -5, y: -7 }, Point { x: 4, y: 2 })),
+                map.extent()
+            );
+        }
+        let index = LineIndex::new(&shifted);
+        assert_eq!(vec![1, 2, 8], index.covering(Point { x: -1, y: -3 }));
+    }
+
+    #[test]
+    fn heatmap_with_negative_coordinates() {
+        let lines = [
+            Line::from_coords(-2, -1, 1, -1),
+            Line::from_coords(-1, -2, -1, 0),
+        ];
+        let map = Map::new(&lines, Coverage::Lattice, Backend::Auto);
+        let ascii = Heatmap {
+            format: HeatmapFormat::Ascii,
+            scale: 1,
+            highlight: None,
+        };
+        let expected = "\
+.1..
+1211
+.1..
+";
+        assert_eq!(
+            expected,
+            String::from_utf8(ascii.render(&map).unwrap()).unwrap()
+        );
+
+        let greyscale = Heatmap {
+            format: HeatmapFormat::Pgm,
+            ..ascii
+        };
+        let image = greyscale.render(&map).unwrap();
+        let header = b"P5\n# origin -2,-2\n4 3\n255\n";
+        assert_eq!(&header[..], &image[..header.len()]);
+        assert_eq!(12, image.len() - header.len());
+        assert_eq!(255, image[header.len() + 5]);
+
+        // lines away from the origin are cropped to
+        let map = Map::new(
+            &[Line::from_coords(1, 1, 2, 1)],
+            Coverage::Lattice,
+            Backend::Auto,
+        );
+        assert_eq!(
+            "11\n",
+            String::from_utf8(ascii.render(&map).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn clusters_example() {
+        let lines = test_lines();
+        let map = test_map();
+        let clusters = find_clusters(&lines, Coverage::Lattice, &map, 2, Connectivity::Four);
+        assert_eq!(
+            vec![3, 3, 2, 1, 1, 1, 1],
+            clusters.iter().map(|c| c.size).collect::<Vec<_>>()
+        );
+        assert_eq!(12, clusters.iter().map(|c| c.size).sum::<usize>());
+        assert_eq!(
+            Cluster {
+                size: 3,
+                min: Point { x: 6, y: 3 },
+                max: Point { x: 7, y: 4 },
+                peak: 3,
+                lines: vec![2, 4, 5, 9],
+            },
+            clusters[0]
+        );
+        assert_eq!(
+            Cluster {
+                size: 3,
+                min: Point { x: 0, y: 9 },
+                max: Point { x: 2, y: 9 },
+                peak: 2,
+                lines: vec![0, 6],
+            },
+            clusters[1]
+        );
+
+        // the diagonal neighbours of (5, 3) and (5, 5) join the two clusters in the middle
+        let clusters = find_clusters(&lines, Coverage::Lattice, &map, 2, Connectivity::Eight);
+        assert_eq!(
+            vec![7, 3, 1, 1],
+            clusters.iter().map(|c| c.size).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            (Point { x: 3, y: 3 }, Point { x: 7, y: 5 }),
+            (clusters[0].min, clusters[0].max)
+        );
+
+        let clusters = find_clusters(&lines, Coverage::Lattice, &map, 3, Connectivity::Eight);
+        assert_eq!(
+            vec![(1, 3), (1, 3)],
+            clusters
                 .iter()
-                .filter_map(|input| input.parse::<Line>().ok())
-                .collect::<Vec<_>>(),
+                .map(|c| (c.size, c.peak))
+                .collect::<Vec<_>>()
         );
-        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn clusters_backends_agree() {
+        let lines = [
+            Line::from_coords(-3, -3, 3, 3),
+            Line::from_coords(-3, 3, 3, -3),
+            Line::from_coords(-3, 0, 3, 0),
+            Line::from_coords(-2, -3, -2, 3),
+            Line::from_coords(-2, -2, -2, 2),
+        ];
+        for connectivity in [Connectivity::Four, Connectivity::Eight] {
+            let find = |backend| {
+                let map = Map::new(&lines, Coverage::Lattice, backend);
+                find_clusters(&lines, Coverage::Lattice, &map, 2, connectivity)
+            };
+            assert_eq!(find(Backend::Dense), find(Backend::Sparse));
+        }
+    }
+
+    #[test_case("add 0,9 -> 5,9", Edit::Add(Line::from_coords(0, 9, 5, 9)))]
+    #[test_case("remove -1,2 -> 3,-4", Edit::Remove(Line::from_coords(-1, 2, 3, -4)))]
+    #[test_case(" add  1,1 -> 1,1 ", Edit::Add(Line::from_coords(1, 1, 1, 1)))]
+    fn edit_parsing(input: &str, expected: Edit) {
+        assert_eq!(expected, input.parse::<Edit>().unwrap());
+    }
+
+    #[test_case("add")]
+    #[test_case("add 1,2")]
+    #[test_case("move 0,0 -> 1,1")]
+    #[test_case(""; "empty")]
+    fn edit_parsing_fails(input: &str) {
+        assert!(input.parse::<Edit>().is_err());
+    }
+
+    #[test]
+    fn incremental_map_example() {
+        let lines = test_lines();
+        let mut map = IncrementalMap::new(Coverage::Lattice, DANGER_THRESHOLD);
+        for line in &lines {
+            map.add(*line);
+        }
+        assert_eq!(12, map.dangerous());
+        for line in lines
+            .iter()
+            .filter(|line| !line.horizontal() && !line.vertical())
+        {
+            assert!(map.remove(line));
+        }
+        assert_eq!(5, map.dangerous());
+        assert!(!map.remove(&Line::from_coords(0, 0, 8, 8)));
+        assert_eq!(5, map.dangerous());
+
+        let input =
+            "add 0,0 -> 8,8\nremove 0,0 -> 8,8\nremove 0,0 -> 8,8\nadd 0,0\n\nadd 1,4 -> 1,9\n";
+        let mut out = vec![];
+        map.run(input.as_bytes(), &mut out).unwrap();
+        let expected = "\
+5
+7
+5
+No such line: 0,0 -> 8,8
+Invalid edit: \"add 0,0\": missing ->
+6
+";
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn incremental_map_matches_batch_randomly() {
+        let mut next = rng(0x1234_5678_9abc_def1);
+        for (coverage, threshold) in [
+            (Coverage::Lattice, 2),
+            (Coverage::Lattice, 3),
+            (Coverage::Raster, 2),
+        ] {
+            let mut map = IncrementalMap::new(coverage, threshold);
+            let mut lines = vec![];
+            for _ in 0..300 {
+                // adds a little more often than it removes, so the map fills up
+                if lines.is_empty() || next(5) < 3 {
+                    let (x, y) = (next(20) - 10, next(20) - 10);
+                    let line = match next(3) {
+                        0 => Line::from_coords(x, y, next(20) - 10, y),
+                        1 => Line::from_coords(x, y, x, next(20) - 10),
+                        _ => Line::from_coords(x, y, next(20) - 10, next(20) - 10),
+                    };
+                    map.add(line);
+                    lines.push(line);
+                } else {
+                    let line = lines.swap_remove(next(lines.len() as u64) as usize);
+                    assert!(map.remove(&line));
+                }
+                assert_eq!(
+                    count_dangerous_points(&lines, coverage, threshold),
+                    map.dangerous(),
+                    "{:?}",
+                    lines
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn heatmap_too_large() {
+        let wide = Map::new(
+            &[
+                Line::from_coords(0, 0, 1, 0),
+                Line::from_coords(1_000_000, 1_000_000, 1_000_001, 1_000_000),
+            ],
+            Coverage::Lattice,
+            Backend::Auto,
+        );
+        let ascii = Heatmap {
+            format: HeatmapFormat::Ascii,
+            scale: 1,
+            highlight: None,
+        };
+        let error = ascii.render(&wide).unwrap_err();
+        assert_eq!(
+            "a 1000002x1000001 heatmap is too large, the most is 67108864 pixels",
+            error.to_string()
+        );
+        // the example fits, until it's scaled up past the limit
+        let greyscale = Heatmap {
+            format: HeatmapFormat::Pgm,
+            scale: 1000,
+            ..ascii
+        };
+        assert!(greyscale.render(&test_map()).is_err());
+        let huge = Heatmap {
+            scale: usize::MAX,
+            ..greyscale
+        };
+        assert!(huge.render(&test_map()).is_err());
+        assert!(ascii.render(&test_map()).is_ok());
+    }
+
+    #[test]
+    fn heatmap_cropped_away_from_the_origin() {
+        let far = Map::new(
+            &[
+                Line::from_coords(1_000_000, 1_000_000, 1_000_002, 1_000_000),
+                Line::from_coords(1_000_001, 1_000_000, 1_000_001, 1_000_001),
+            ],
+            Coverage::Lattice,
+            Backend::Auto,
+        );
+        let ascii = Heatmap {
+            format: HeatmapFormat::Ascii,
+            scale: 1,
+            highlight: None,
+        };
+        assert_eq!(
+            "121\n.1.\n",
+            String::from_utf8(ascii.render(&far).unwrap()).unwrap()
+        );
+        let greyscale = Heatmap {
+            format: HeatmapFormat::Pgm,
+            ..ascii
+        };
+        let image = greyscale.render(&far).unwrap();
+        let header = b"P5\n# origin 1000000,1000000\n3 2\n255\n";
+        assert_eq!(&header[..], &image[..header.len()]);
+        assert_eq!(6, image.len() - header.len());
+    }
+
+    #[test]
+    fn heat_colours() {
+        assert_eq!([0, 0, 80], heat_colour(0.0));
+        assert_eq!([230, 30, 30], heat_colour(2.0 / 3.0));
+        assert_eq!([255, 230, 0], heat_colour(1.0));
+        assert_eq!([255, 230, 0], heat_colour(7.0));
+    }
+
+    #[test_case("point 7,4", &[3, 5])]
+    #[test_case("point 4,4", &[2, 3, 9])]
+    #[test_case("point 9,9", &[])]
+    #[test_case("rect 6,1 9,3", &[2, 5, 10])]
+    #[test_case("rect 9,3 6,1", &[2, 5, 10])]
+    #[test_case("rect 0,0 9,9", &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10])]
+    #[test_case("line 0,5 -> 5,0", &[6, 8, 9])]
+    #[test_case("line 4,9 -> 4,9", &[1])]
+    #[test_case("rect -100000000,-100000000 100000000,100000000", &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10])]
+    #[test_case("point 100000000,100000000", &[])]
+    #[test_case("line -100000000,100000000 -> 100000000,-100000000", &[9])]
+    fn query_example(query: &str, expected: &[usize]) {
+        let lines = test_lines();
+        let found = LineIndex::new(&lines).query(&query.parse().unwrap());
+        assert_eq!(
+            expected,
+            found.iter().map(|idx| idx + 1).collect::<Vec<_>>()
+        );
+    }
+
+    #[test_case("point 1")]
+    #[test_case("rect 1,2")]
+    #[test_case("line 1,2 3,4")]
+    #[test_case("circle 1,2")]
+    fn query_invalid(query: &str) {
+        assert!(query.parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn line_display() {
+        assert_eq!(
+            "8,0 -> 0,8",
+            TEST_INPUT[1].parse::<Line>().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn segments_intersect() {
+        let segment = |x1, y1, x2, y2| Segment::from(&Line::from_coords(x1, y1, x2, y2));
+        // crossing between lattice points still counts
+        assert!(segment(0, 0, 3, 1).intersects(&segment(0, 1, 3, 0)));
+        assert!(segment(0, 0, 4, 0).intersects(&segment(4, 0, 9, 9)));
+        assert!(segment(0, 0, 4, 0).intersects(&segment(2, 0, 7, 0)));
+        assert!(!segment(0, 0, 4, 0).intersects(&segment(5, 0, 7, 0)));
+        assert!(!segment(0, 0, 4, 4).intersects(&segment(1, 0, 5, 4)));
+        assert!(segment(2, 2, 2, 2).intersects(&segment(0, 0, 4, 4)));
+        assert!(segment(0, 5, 5, 0).intersects_rect((1, 1), (3, 3)));
+        assert!(!segment(0, 7, 7, 0).intersects_rect((1, 1), (3, 3)));
+        assert!(segment(2, 2, 2, 2).intersects_rect((1, 1), (3, 3)));
+        assert!(segment(0, 0, 9, 0).intersects_rect((3, 0), (4, 0)));
+    }
+
+    #[test]
+    fn index_matches_scanning_every_line() {
+        let mut next = rng(0x1234_5678_9abc_def1);
+        let lines = (0..300)
+            .map(|_| {
+                Line::from_coords(
+                    next(200) - 100,
+                    next(200) - 100,
+                    next(200) - 100,
+                    next(200) - 100,
+                )
+            })
+            .collect::<Vec<_>>();
+        let segments = lines.iter().map(Segment::from).collect::<Vec<_>>();
+        let scan = |check: &dyn Fn(&Segment) -> bool| {
+            (0..lines.len())
+                .filter(|idx| check(&segments[*idx]))
+                .collect::<Vec<_>>()
+        };
+        let index = LineIndex::new(&lines);
+        for _ in 0..200 {
+            let point = Point {
+                x: next(300) - 150,
+                y: next(300) - 150,
+            };
+            let p = (point.x as i128, point.y as i128);
+            assert_eq!(scan(&|s| s.contains(p)), index.covering(point));
+
+            let (corner, opposite) = (
+                Point {
+                    x: next(200),
+                    y: next(200),
+                },
+                point,
+            );
+            let lo = (
+                corner.x.min(opposite.x) as i128,
+                corner.y.min(opposite.y) as i128,
+            );
+            let hi = (
+                corner.x.max(opposite.x) as i128,
+                corner.y.max(opposite.y) as i128,
+            );
+            assert_eq!(
+                scan(&|s| s.intersects_rect(lo, hi)),
+                index.in_rect(corner, opposite)
+            );
+
+            let line = Line {
+                start: corner,
+                end: opposite,
+            };
+            let query = Segment::from(&line);
+            assert_eq!(scan(&|s| s.intersects(&query)), index.intersecting(&line));
+        }
     }
 }
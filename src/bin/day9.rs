@@ -72,28 +72,27 @@ impl Heightmap {
     fn total_risk(&self) -> usize {
         self.holes()
             .into_iter()
-            .filter_map(|(x, y)| self.positions.get(x).map(|row| row.get(y)).flatten())
+            .filter_map(|(x, y)| self.positions.get(x).and_then(|row| row.get(y)))
             .map(|height| *height + 1)
             .sum()
     }
 
     fn holes(&self) -> Vec<(usize, usize)> {
         (0..self.positions.len())
-            .cartesian_product(0..self.positions.get(0).map(Vec::len).unwrap_or_default())
+            .cartesian_product(0..self.positions.first().map(Vec::len).unwrap_or_default())
             .filter(|(x, y)| self.is_hole(*x, *y))
             .collect()
     }
 
     fn is_hole(&self, x: usize, y: usize) -> bool {
-        let height = match self.positions.get(x).map(|row| row.get(y)).flatten() {
+        let height = match self.positions.get(x).and_then(|row| row.get(y)) {
             Some(height) => *height,
             None => panic!("out of bounds"),
         };
         let height_above = if x > 0 {
             self.positions
                 .get(x - 1)
-                .map(|row| row.get(y))
-                .flatten()
+                .and_then(|row| row.get(y))
                 .copied()
         } else {
             None
@@ -101,8 +100,7 @@ impl Heightmap {
         let height_below = if x < self.positions.len() - 1 {
             self.positions
                 .get(x + 1)
-                .map(|row| row.get(y))
-                .flatten()
+                .and_then(|row| row.get(y))
                 .copied()
         } else {
             None
@@ -110,20 +108,18 @@ impl Heightmap {
         let height_left = self
             .positions
             .get(x)
-            .map(|row| if y > 0 { row.get(y - 1) } else { None })
-            .flatten()
+            .and_then(|row| if y > 0 { row.get(y - 1) } else { None })
             .copied();
         let height_right = self
             .positions
             .get(x)
-            .map(|row| {
+            .and_then(|row| {
                 if y < row.len() - 1 {
                     row.get(y + 1)
                 } else {
                     None
                 }
             })
-            .flatten()
             .copied();
 
         height < height_above.unwrap_or(usize::MAX)
@@ -137,7 +133,7 @@ impl Heightmap {
 
         // above
         if x > 0 {
-            if let Some(height) = self.positions.get(x - 1).map(|row| row.get(y)).flatten() {
+            if let Some(height) = self.positions.get(x - 1).and_then(|row| row.get(y)) {
                 if *height != 9 {
                     points.push((x - 1, y));
                 }
@@ -146,7 +142,7 @@ impl Heightmap {
 
         // below
         if x < self.positions.len() - 1 {
-            if let Some(height) = self.positions.get(x + 1).map(|row| row.get(y)).flatten() {
+            if let Some(height) = self.positions.get(x + 1).and_then(|row| row.get(y)) {
                 if *height != 9 {
                     points.push((x + 1, y));
                 }
@@ -155,7 +151,7 @@ impl Heightmap {
 
         // left
         if y > 0 {
-            if let Some(height) = self.positions.get(x).map(|row| row.get(y - 1)).flatten() {
+            if let Some(height) = self.positions.get(x).and_then(|row| row.get(y - 1)) {
                 if *height != 9 {
                     points.push((x, y - 1));
                 }
@@ -169,7 +165,7 @@ impl Heightmap {
             .map(|row| row.len() - 1)
             .unwrap_or_default()
         {
-            if let Some(height) = self.positions.get(x).map(|row| row.get(y + 1)).flatten() {
+            if let Some(height) = self.positions.get(x).and_then(|row| row.get(y + 1)) {
                 if *height != 9 {
                     points.push((x, y + 1));
                 }
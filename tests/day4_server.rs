@@ -0,0 +1,186 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn join(addr: &str, name: &str) -> Client {
+        let writer = TcpStream::connect(addr).unwrap();
+        writer
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+        let mut client = Client {
+            reader: BufReader::new(writer.try_clone().unwrap()),
+            writer,
+        };
+        client.send(&format!("JOIN {}", name));
+        assert!(client.receive().unwrap().starts_with("WELCOME "));
+        client
+    }
+
+    fn send(&mut self, message: &str) {
+        writeln!(self.writer, "{}", message).unwrap();
+    }
+
+    fn receive(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line).unwrap() {
+            0 => None,
+            _ => Some(line.trim_end().to_string()),
+        }
+    }
+}
+
+struct Board {
+    rows: usize,
+    cols: usize,
+    squares: Vec<usize>,
+}
+
+impl Board {
+    fn parse(message: &str) -> Board {
+        let mut words = message.split(' ');
+        assert_eq!(Some("BOARD"), words.next());
+        let (rows, cols) = words.next().unwrap().split_once('x').unwrap();
+        Board {
+            rows: rows.parse().unwrap(),
+            cols: cols.parse().unwrap(),
+            squares: words
+                .next()
+                .unwrap()
+                .split(',')
+                .map(|n| n.parse().unwrap())
+                .collect(),
+        }
+    }
+
+    fn finished(&self, drawn: &[usize]) -> bool {
+        let marked = |row: usize, col: usize| drawn.contains(&self.squares[row * self.cols + col]);
+        (0..self.rows).any(|row| (0..self.cols).all(|col| marked(row, col)))
+            || (0..self.cols).any(|col| (0..self.rows).all(|row| marked(row, col)))
+    }
+
+    fn score(&self, drawn: &[usize]) -> usize {
+        let unmarked = self.squares.iter().filter(|n| !drawn.contains(n));
+        unmarked.sum::<usize>() * drawn.last().unwrap()
+    }
+}
+
+// plays until the server ends the game, claiming as soon as the board is finished and, if
+// `bluff`, once straight after the first draw; returns the board, the draws and everything else
+// the server sent
+fn play(mut client: Client, bluff: bool) -> (Board, Vec<usize>, Vec<String>) {
+    let board = Board::parse(&client.receive().unwrap());
+    let mut drawn = vec![];
+    let mut messages = vec![];
+    let mut claimed = false;
+    while let Some(message) = client.receive() {
+        if let Some(number) = message.strip_prefix("DRAW ") {
+            drawn.push(number.parse().unwrap());
+            if bluff && drawn.len() == 1 {
+                client.send("BINGO");
+            }
+            if !claimed && board.finished(&drawn) {
+                client.send("BINGO");
+                claimed = true;
+            }
+            continue;
+        }
+        let end = message == "END";
+        messages.push(message);
+        if end {
+            break;
+        }
+    }
+    (board, drawn, messages)
+}
+
+#[test]
+fn test_bingo_server() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_day4"))
+        .args([
+            "--serve",
+            "0",
+            "--players",
+            "3",
+            "--speed",
+            "50",
+            "--seed",
+            "7",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut output = BufReader::new(server.stdout.take().unwrap());
+    let mut line = String::new();
+    output.read_line(&mut line).unwrap();
+    let addr = line
+        .trim()
+        .strip_prefix("Listening on ")
+        .unwrap()
+        .to_string();
+
+    // a connection that doesn't join is turned away without taking a seat
+    let writer = TcpStream::connect(&addr).unwrap();
+    let mut stranger = Client {
+        reader: BufReader::new(writer.try_clone().unwrap()),
+        writer,
+    };
+    stranger.send("HELLO");
+    assert_eq!(
+        Some("ERROR expected JOIN <name>".to_string()),
+        stranger.receive()
+    );
+
+    let names = ["alice", "bob", "carol"];
+    let clients = names
+        .iter()
+        .map(|name| Client::join(&addr, name))
+        .collect::<Vec<_>>();
+    let games = clients
+        .into_iter()
+        .enumerate()
+        .map(|(idx, client)| thread::spawn(move || play(client, idx == 0)))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|game| game.join().unwrap())
+        .collect::<Vec<_>>();
+
+    let (_, _, messages) = &games[0];
+    assert_eq!(
+        Some("REJECTED not a bingo"),
+        messages.first().map(String::as_str)
+    );
+    let winner = messages
+        .iter()
+        .find(|m| m.starts_with("WINNER "))
+        .unwrap()
+        .clone();
+    for (_, _, messages) in &games {
+        assert_eq!(
+            vec![winner.as_str(), "END"],
+            messages
+                .iter()
+                .filter(|m| !m.starts_with("REJECTED"))
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    let mut words = winner.split(' ').skip(1);
+    let name = words.next().unwrap();
+    let score = words.next().unwrap().parse::<usize>().unwrap();
+    let idx = names.iter().position(|n| *n == name).unwrap();
+    let (board, drawn, _) = &games[idx];
+    assert!(board.finished(drawn));
+    assert_eq!(score, board.score(drawn));
+
+    output.read_line(&mut line).unwrap();
+    assert!(server.wait().unwrap().success());
+}